@@ -0,0 +1,89 @@
+//! xml decode throughput on the test fixtures, run with `cargo bench --bench decode`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tak_rs::protocol::xml::CotLegacyCodec;
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+const FIXTURES: [&[u8]; 6] = [
+    include_bytes!("../src/protocol/xml/fixtures/first_event.xml"),
+    include_bytes!("../src/protocol/xml/fixtures/additional.xml"),
+    include_bytes!("../src/protocol/xml/fixtures/911_alert_start.xml"),
+    include_bytes!("../src/protocol/xml/fixtures/911_deactive.xml"),
+    include_bytes!("../src/protocol/xml/fixtures/contact_alert.xml"),
+    include_bytes!("../src/protocol/xml/fixtures/general_chat_message.xml"),
+];
+
+/// connection is limited to this many bytes per frame in the server
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+/// typical TCP segment payload
+const MSS: usize = 1460;
+
+/// feeds `stream` to decoder in `chunk` sized reads like a socket would, returns decoded count
+fn decode_in_chunks(stream: &[u8], chunk: usize) -> usize {
+    let mut decoder = CotLegacyCodec::new(MAX_FRAME_SIZE);
+    let mut buffer = BytesMut::with_capacity(8 * 1024);
+    let mut decoded = 0;
+    for read in stream.chunks(chunk) {
+        buffer.extend_from_slice(read);
+        while decoder.decode(&mut buffer).expect("valid stream").is_some() {
+            decoded += 1;
+        }
+    }
+    decoded
+}
+
+fn fixture_stream(repeat: usize) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for _ in 0..repeat {
+        for fixture in FIXTURES {
+            stream.extend_from_slice(fixture);
+            stream.push(b'\n');
+        }
+    }
+    stream
+}
+
+/// single event padded with remarks to almost the frame limit
+fn large_event() -> Vec<u8> {
+    let remarks = "x".repeat(MAX_FRAME_SIZE - 1024);
+    format!(
+        r#"<event version="2.0" uid="large" type="b-t-f" how="h-g-i-g-o" time="2023-12-23T19:25:49Z" start="2023-12-23T19:25:49Z" stale="2023-12-23T19:27:49Z"><point lat="0.0" lon="0.0" hae="0.0" ce="9999999.0" le="9999999.0"/><detail><remarks>{remarks}</remarks></detail></event>"#
+    )
+    .into_bytes()
+}
+
+fn fixtures(c: &mut Criterion) {
+    let repeat = 100;
+    let stream = fixture_stream(repeat);
+    let messages = repeat * FIXTURES.len();
+    let mut group = c.benchmark_group("xml_decode_fixtures");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    // one read per message is what a quiet client looks like, segments are a busy one
+    let message_sized = stream.len() / messages;
+    for (name, chunk) in [
+        ("message", message_sized),
+        ("mss", MSS),
+        ("batch", 64 * 1024),
+    ] {
+        group.bench_with_input(BenchmarkId::new("chunk", name), &chunk, |b, &chunk| {
+            b.iter(|| assert_eq!(decode_in_chunks(&stream, chunk), messages))
+        });
+    }
+    group.finish();
+}
+
+fn large_frame(c: &mut Criterion) {
+    let event = large_event();
+    let mut group = c.benchmark_group("xml_decode_large_frame");
+    group.throughput(Throughput::Bytes(event.len() as u64));
+    for chunk in [64, MSS] {
+        group.bench_with_input(BenchmarkId::new("chunk", chunk), &chunk, |b, &chunk| {
+            b.iter(|| assert_eq!(decode_in_chunks(&event, chunk), 1))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fixtures, large_frame);
+criterion_main!(benches);
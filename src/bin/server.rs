@@ -1,22 +1,106 @@
-use tak_rs::server::{Config, Server};
-use tak_rs::tls;
-use tracing::metadata::LevelFilter;
+use clap::Parser;
+use std::path::PathBuf;
+use tak_rs::config::{FileConfig, InputSection};
+use tak_rs::server::Server;
+use tokio::signal;
+use tracing::{error, info};
 
 use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+/// TAK server, options override values from configuration file
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// TOML configuration file, see server.example.toml
+    #[arg(short, long, env = "TAK_CONFIG")]
+    config: Option<PathBuf>,
+    /// error, warn, info, debug, trace or off
+    #[arg(long, env = "TAK_LOG_LEVEL")]
+    log_level: Option<String>,
+    /// replaces inputs of configuration file, e.g. `tls://[::]:8089,udp://0.0.0.0:8087`
+    #[arg(long, env = "TAK_LISTEN", value_delimiter = ',')]
+    listen: Vec<InputSection>,
+    #[arg(long, env = "TAK_TLS_CA")]
+    tls_ca: Option<String>,
+    #[arg(long, env = "TAK_TLS_CERT")]
+    tls_cert: Option<String>,
+    #[arg(long, env = "TAK_TLS_KEY")]
+    tls_key: Option<String>,
+    #[arg(long, env = "TAK_MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+    /// largest xml event read from streaming clients, in bytes
+    #[arg(long, env = "TAK_MAX_XML_FRAME_SIZE")]
+    max_xml_frame_size: Option<usize>,
+    /// largest TAK protocol payload read from streaming clients, in bytes
+    #[arg(long, env = "TAK_MAX_PROTO_FRAME_SIZE")]
+    max_proto_frame_size: Option<usize>,
+}
+
+impl Cli {
+    fn into_file_config(self) -> anyhow::Result<FileConfig> {
+        let mut config = match &self.config {
+            Some(path) => FileConfig::load(path)?,
+            None => FileConfig::default(),
+        };
+        if let Some(level) = self.log_level {
+            config.logging.level = level;
+        }
+        if !self.listen.is_empty() {
+            config.inputs = self.listen;
+        }
+        if let Some(ca) = self.tls_ca {
+            config.tls_mut().ca = ca;
+        }
+        if let Some(cert) = self.tls_cert {
+            config.tls_mut().cert = cert;
+        }
+        if let Some(key) = self.tls_key {
+            config.tls_mut().key = key;
+        }
+        if let Some(max_connections) = self.max_connections {
+            config.limits.max_connections = max_connections;
+        }
+        if let Some(max_xml_size) = self.max_xml_frame_size {
+            config.frame_limits.max_xml_size = max_xml_size;
+        }
+        if let Some(max_proto_size) = self.max_proto_frame_size {
+            config.frame_limits.max_proto_size = max_proto_size;
+        }
+        Ok(config)
+    }
+}
+
+/// completes on SIGINT or, on unix, SIGTERM
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            res = signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tak_rs::tracing::init(LevelFilter::DEBUG)?;
-    let server = Server::new(Config {
-        listen_port: 8089,
-        tls: tls::Config {
-            ca: "certs/ca.crt".to_string(),
-            cert: "certs/server.crt".to_string(),
-            key: "certs/server.key".to_string(),
-        },
-    })?;
+    let config = Cli::parse().into_file_config()?;
+    tak_rs::tracing::init(config.log_level()?)?;
+    let server = Server::new(config.into_server_config()?)?;
 
+    let shutdown = server.shutdown_token();
+    tokio::spawn(async move {
+        match shutdown_signal().await {
+            Ok(()) => {
+                info!("Shutdown requested");
+                shutdown.cancel();
+            }
+            Err(err) => error!("Can not listen for shutdown signals: {err}"),
+        }
+    });
     server.run().await
 }
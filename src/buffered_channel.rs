@@ -1,12 +1,19 @@
 use tokio::{
     select,
-    sync::broadcast::{channel as broadcast_channel, error::RecvError, Receiver, Sender},
+    sync::broadcast::{
+        channel as broadcast_channel,
+        error::{RecvError, TryRecvError},
+        Receiver, Sender,
+    },
     task::JoinHandle,
 };
 use tracing::warn;
 
 pub struct BufferedReceiver<T> {
     receiver: Receiver<T>,
+    /// items beyond it are skipped oldest first, broadcast channel would keep
+    /// up to its capacity rounded to a power of two
+    max_queued: Option<usize>,
     _handle: JoinHandle<()>,
     _stopper: tokio::sync::oneshot::Sender<()>,
 }
@@ -34,11 +41,20 @@ impl<T: Clone + Send + 'static> BufferedReceiver<T> {
         Self {
             _handle: task_handle,
             receiver: buffer_consumer,
+            max_queued: None,
             _stopper: stopper,
         }
     }
 
     pub async fn read_next(&mut self) -> Option<T> {
+        if let Some(max_queued) = self.max_queued {
+            // skipped items are counted by sender, overwritten ones count as queued as well
+            while self.receiver.len() > max_queued {
+                if let Err(TryRecvError::Empty | TryRecvError::Closed) = self.receiver.try_recv() {
+                    break;
+                }
+            }
+        }
         loop {
             match self.receiver.recv().await {
                 Ok(v) => return Some(v),
@@ -60,6 +76,96 @@ where
     (sender, BufferedReceiver::new(receiver, receiver2))
 }
 
+/// what to do with items when receiver is not keeping up and buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum OverflowPolicy {
+    /// oldest buffered item is dropped to make space for the new one
+    DropOldest,
+    /// new item is dropped, buffered ones are kept
+    DropNewest,
+    /// same as `DropNewest`, but sender gives up after `max_lagged` items dropped in a row,
+    /// any queued item starts the count over
+    Disconnect { max_lagged: u64 },
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SendError {
+    #[error("receiver closed")]
+    Closed,
+    #[error("receiver lagged behind by {0} items")]
+    Lagged(u64),
+}
+
+/// sender which applies [`OverflowPolicy`] and counts items lost by the receiver
+pub struct BufferedSender<T> {
+    sender: Sender<T>,
+    buffer_size: usize,
+    policy: OverflowPolicy,
+    lagged: u64,
+    /// items dropped since the last one which was queued
+    dropped_in_row: u64,
+}
+
+impl<T> BufferedSender<T> {
+    /// returns true if item was queued, false if it was dropped by policy
+    pub fn send(&mut self, item: T) -> Result<bool, SendError> {
+        let queued = self.sender.len();
+        let full = queued >= self.buffer_size;
+
+        if full {
+            self.lagged += 1;
+            self.dropped_in_row += 1;
+            match self.policy {
+                // receiver skips items beyond buffer size
+                OverflowPolicy::DropOldest => {}
+                OverflowPolicy::DropNewest => return Ok(false),
+                OverflowPolicy::Disconnect { max_lagged } if self.dropped_in_row >= max_lagged => {
+                    return Err(SendError::Lagged(self.dropped_in_row))
+                }
+                OverflowPolicy::Disconnect { .. } => return Ok(false),
+            }
+        }
+
+        self.sender.send(item).map_err(|_| SendError::Closed)?;
+        if !full {
+            self.dropped_in_row = 0;
+        }
+        Ok(!full)
+    }
+
+    /// total count of items lost because of overflow
+    pub fn lagged(&self) -> u64 {
+        self.lagged
+    }
+
+    /// count of items waiting to be read by receiver
+    pub fn queued(&self) -> usize {
+        self.sender.len().min(self.buffer_size)
+    }
+}
+
+pub fn channel_with_policy<T>(
+    buffer_size: usize,
+    policy: OverflowPolicy,
+) -> (BufferedSender<T>, BufferedReceiver<T>)
+where
+    T: Clone + Send + 'static,
+{
+    let (sender, mut receiver) = channel(buffer_size);
+    receiver.max_queued = Some(buffer_size);
+    (
+        BufferedSender {
+            sender,
+            buffer_size,
+            policy,
+            lagged: 0,
+            dropped_in_row: 0,
+        },
+        receiver,
+    )
+}
+
 #[cfg(test)]
 mod test {
     use tokio::{sync::broadcast::error::SendError, task::yield_now};
@@ -107,4 +213,87 @@ mod test {
         assert_eq!(receiver.read_next().await.unwrap(), 5);
         assert_eq!(receiver.read_next().await, None)
     }
+
+    #[tokio::test]
+    async fn test_drop_oldest_policy_counts_lagged() {
+        let (mut sender, mut receiver) = channel_with_policy::<u32>(4, OverflowPolicy::DropOldest);
+        for i in 1..=6 {
+            sender.send(i).expect("sent");
+        }
+        yield_now().await;
+        assert_eq!(sender.lagged(), 2);
+        assert_eq!(receiver.read_next().await.unwrap(), 3);
+        assert_eq!(receiver.read_next().await.unwrap(), 4);
+        assert_eq!(receiver.read_next().await.unwrap(), 5);
+        assert_eq!(receiver.read_next().await.unwrap(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_policy_keeps_configured_size() {
+        // broadcast channel itself would keep 4 items
+        let (mut sender, mut receiver) = channel_with_policy::<u32>(3, OverflowPolicy::DropOldest);
+        for i in 1..=6 {
+            assert_eq!(sender.send(i), Ok(i <= 3));
+        }
+        yield_now().await;
+        assert_eq!(sender.lagged(), 3);
+        assert_eq!(sender.queued(), 3);
+        assert_eq!(receiver.read_next().await.unwrap(), 4);
+        assert_eq!(receiver.read_next().await.unwrap(), 5);
+        assert_eq!(receiver.read_next().await.unwrap(), 6);
+        assert_eq!(sender.send(7), Ok(true));
+        assert_eq!(receiver.read_next().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest_policy_keeps_buffered() {
+        let (mut sender, mut receiver) = channel_with_policy::<u32>(3, OverflowPolicy::DropNewest);
+        assert_eq!(sender.send(1), Ok(true));
+        assert_eq!(sender.send(2), Ok(true));
+        assert_eq!(sender.send(3), Ok(true));
+        assert_eq!(sender.send(4), Ok(false));
+        assert_eq!(sender.send(5), Ok(false));
+        yield_now().await;
+        assert_eq!(sender.lagged(), 2);
+        assert_eq!(receiver.read_next().await.unwrap(), 1);
+        assert_eq!(receiver.read_next().await.unwrap(), 2);
+        assert_eq!(receiver.read_next().await.unwrap(), 3);
+        assert_eq!(sender.send(6), Ok(true));
+        assert_eq!(receiver.read_next().await.unwrap(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_policy_gives_up() {
+        let (mut sender, _receiver) =
+            channel_with_policy::<u32>(2, OverflowPolicy::Disconnect { max_lagged: 2 });
+        assert_eq!(sender.send(1), Ok(true));
+        assert_eq!(sender.send(2), Ok(true));
+        assert_eq!(sender.send(3), Ok(false));
+        assert_eq!(sender.send(4), Err(super::SendError::Lagged(2)));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_policy_forgives_drained_drops() {
+        let (mut sender, mut receiver) =
+            channel_with_policy::<u32>(2, OverflowPolicy::Disconnect { max_lagged: 2 });
+        assert_eq!(sender.send(1), Ok(true));
+        assert_eq!(sender.send(2), Ok(true));
+        assert_eq!(sender.send(3), Ok(false));
+        assert_eq!(receiver.read_next().await.unwrap(), 1);
+        assert_eq!(receiver.read_next().await.unwrap(), 2);
+        yield_now().await;
+        assert_eq!(sender.send(4), Ok(true));
+        assert_eq!(sender.send(5), Ok(true));
+        assert_eq!(sender.send(6), Ok(false));
+        assert_eq!(sender.lagged(), 2);
+        assert_eq!(sender.send(7), Err(super::SendError::Lagged(2)));
+    }
+
+    #[tokio::test]
+    async fn test_send_to_closed_receiver() {
+        let (mut sender, receiver) = channel_with_policy::<u32>(2, OverflowPolicy::DropNewest);
+        drop(receiver);
+        yield_now().await;
+        assert_eq!(sender.send(1), Err(super::SendError::Closed));
+    }
 }
@@ -0,0 +1,570 @@
+//! TOML configuration of the server binary
+
+use crate::connection::{Direction, FrameLimits, Protocol, Timeouts};
+use crate::multicast;
+use crate::protocol::xml::XmlLimits;
+use crate::rate_limit::RateLimit;
+use crate::router::{Limits, OutboundQueueConfig};
+use crate::server::{self, Input, Transport};
+use crate::tls;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("can not read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("{field}: {reason}")]
+    Invalid { field: String, reason: String },
+}
+
+fn invalid(field: impl Into<String>, reason: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        field: field.into(),
+        reason: reason.into(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransportKind {
+    Tls,
+    Tcp,
+    Udp,
+}
+
+/// `[[inputs]]` entry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputSection {
+    pub bind: SocketAddr,
+    pub transport: TransportKind,
+    #[serde(default)]
+    pub ipv6_only: bool,
+    /// streaming inputs only
+    pub protocol: Option<Protocol>,
+    /// streaming inputs only
+    pub direction: Option<Direction>,
+    /// streaming inputs only
+    pub lenient_xml: Option<bool>,
+    /// TLS inputs only, server wide `[tls]` is used when missing
+    pub tls: Option<tls::Config>,
+    /// UDP inputs only
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl InputSection {
+    fn new(transport: TransportKind, bind: SocketAddr) -> Self {
+        Self {
+            bind,
+            transport,
+            ipv6_only: false,
+            protocol: None,
+            direction: None,
+            lenient_xml: None,
+            tls: None,
+            rate_limit: None,
+        }
+    }
+}
+
+/// short form used on command line, e.g. `tls://[::]:8089` or `udp://0.0.0.0:8087`
+impl FromStr for InputSection {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (transport, bind) = s
+            .split_once("://")
+            .ok_or_else(|| invalid(s, "expected <transport>://<address>:<port>"))?;
+        let transport = match transport {
+            "tls" => TransportKind::Tls,
+            "tcp" => TransportKind::Tcp,
+            "udp" => TransportKind::Udp,
+            other => return Err(invalid(s, format!("unknown transport {other}"))),
+        };
+        let bind = bind
+            .parse()
+            .map_err(|e| invalid(s, format!("invalid address: {e}")))?;
+        Ok(Self::new(transport, bind))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    /// default level, `RUST_LOG` directives still apply on top of it
+    pub level: String,
+}
+
+impl Default for LoggingSection {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownSection {
+    /// seconds connections get to send queued messages after SIGTERM or SIGINT
+    pub grace_period_secs: u64,
+}
+
+impl Default for ShutdownSection {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: server::DEFAULT_SHUTDOWN_GRACE_PERIOD.as_secs(),
+        }
+    }
+}
+
+/// zero disables the timeout
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsSection {
+    pub tls_handshake_secs: u64,
+    pub first_event_secs: u64,
+    pub idle_secs: u64,
+    pub ping_interval_secs: u64,
+}
+
+fn as_secs(timeout: Option<Duration>) -> u64 {
+    timeout.map_or(0, |timeout| timeout.as_secs())
+}
+
+fn from_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+impl Default for TimeoutsSection {
+    fn default() -> Self {
+        let timeouts = Timeouts::default();
+        Self {
+            tls_handshake_secs: as_secs(timeouts.tls_handshake),
+            first_event_secs: as_secs(timeouts.first_event),
+            idle_secs: as_secs(timeouts.idle),
+            ping_interval_secs: as_secs(timeouts.ping_interval),
+        }
+    }
+}
+
+/// whole configuration file, every section is optional
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub logging: LoggingSection,
+    pub tls: Option<tls::Config>,
+    pub inputs: Vec<InputSection>,
+    pub limits: Limits,
+    pub outbound_queue: OutboundQueueConfig,
+    pub mesh_bridge: Option<multicast::Config>,
+    pub timeouts: TimeoutsSection,
+    pub xml_limits: XmlLimits,
+    pub frame_limits: FrameLimits,
+    pub shutdown: ShutdownSection,
+}
+
+fn default_tls() -> tls::Config {
+    tls::Config {
+        ca: "certs/ca.crt".to_string(),
+        cert: "certs/server.crt".to_string(),
+        key: "certs/server.key".to_string(),
+    }
+}
+
+/// mTLS on 8089, dual-stack; unauthenticated inputs have to be configured explicitly
+impl Default for FileConfig {
+    fn default() -> Self {
+        Self {
+            logging: Default::default(),
+            tls: Some(default_tls()),
+            inputs: vec![InputSection::new(
+                TransportKind::Tls,
+                "[::]:8089".parse().expect("valid address"),
+            )],
+            limits: Default::default(),
+            outbound_queue: Default::default(),
+            mesh_bridge: None,
+            timeouts: Default::default(),
+            xml_limits: Default::default(),
+            frame_limits: Default::default(),
+            shutdown: Default::default(),
+        }
+    }
+}
+
+fn check_tls(field: &str, tls: &tls::Config) -> Result<(), ConfigError> {
+    for (name, path) in [("ca", &tls.ca), ("cert", &tls.cert), ("key", &tls.key)] {
+        if !Path::new(path).is_file() {
+            return Err(invalid(
+                format!("{field}.{name}"),
+                format!("file not found: {path}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_rate_limit(field: &str, rate_limit: &RateLimit) -> Result<(), ConfigError> {
+    if rate_limit.per_second <= 0.0 || rate_limit.burst == 0 {
+        return Err(invalid(field, "per_second and burst must be positive"));
+    }
+    Ok(())
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        content.parse()
+    }
+
+    /// server wide TLS section, created with default paths if missing
+    pub fn tls_mut(&mut self) -> &mut tls::Config {
+        self.tls.get_or_insert_with(default_tls)
+    }
+
+    pub fn log_level(&self) -> Result<LevelFilter, ConfigError> {
+        self.logging.level.parse().map_err(|_| {
+            invalid(
+                "logging.level",
+                format!("unknown level {}", self.logging.level),
+            )
+        })
+    }
+
+    /// checks everything that can be checked before binding sockets
+    pub fn into_server_config(self) -> Result<server::Config, ConfigError> {
+        if self.inputs.is_empty() {
+            return Err(invalid("inputs", "at least one input expected"));
+        }
+        if self.limits.max_connections == 0 {
+            return Err(invalid("limits.max_connections", "must be positive"));
+        }
+        if self.outbound_queue.size == 0 {
+            return Err(invalid("outbound_queue.size", "must be positive"));
+        }
+        let xml_limits = self.xml_limits;
+        for (name, limit) in [
+            ("max_depth", xml_limits.max_depth),
+            ("max_elements", xml_limits.max_elements),
+            ("max_attributes", xml_limits.max_attributes),
+            ("max_text_len", xml_limits.max_text_len),
+        ] {
+            if limit == 0 {
+                return Err(invalid(format!("xml_limits.{name}"), "must be positive"));
+            }
+        }
+        let frame_limits = self.frame_limits;
+        for (name, limit) in [
+            ("max_xml_size", frame_limits.max_xml_size),
+            ("max_proto_size", frame_limits.max_proto_size),
+        ] {
+            if limit == 0 {
+                return Err(invalid(format!("frame_limits.{name}"), "must be positive"));
+            }
+        }
+        if let Some(mesh_bridge) = &self.mesh_bridge {
+            if !mesh_bridge.group.ip().is_multicast() {
+                return Err(invalid(
+                    "mesh_bridge.group",
+                    format!("{} is not a multicast address", mesh_bridge.group.ip()),
+                ));
+            }
+            check_rate_limit("mesh_bridge.rate_limit", &mesh_bridge.rate_limit)?;
+        }
+
+        let mut bound = HashSet::new();
+        let mut inputs = Vec::with_capacity(self.inputs.len());
+        for (index, section) in self.inputs.into_iter().enumerate() {
+            let field = format!("inputs[{index}]");
+            let is_udp = section.transport == TransportKind::Udp;
+            if !bound.insert((is_udp, section.bind)) {
+                return Err(invalid(
+                    field,
+                    format!("{} is already used by another input", section.bind),
+                ));
+            }
+            if section.tls.is_some() && section.transport != TransportKind::Tls {
+                return Err(invalid(
+                    format!("{field}.tls"),
+                    "only TLS inputs have certificates",
+                ));
+            }
+            if section.rate_limit.is_some() && !is_udp {
+                return Err(invalid(
+                    format!("{field}.rate_limit"),
+                    "only UDP inputs are rate limited",
+                ));
+            }
+            if is_udp && (section.protocol.is_some() || section.direction.is_some()) {
+                return Err(invalid(
+                    field,
+                    "UDP input accepts both protocols and is always read-only",
+                ));
+            }
+            if is_udp && section.lenient_xml.is_some() {
+                return Err(invalid(
+                    format!("{field}.lenient_xml"),
+                    "UDP input always skips malformed datagrams",
+                ));
+            }
+
+            let transport = match section.transport {
+                TransportKind::Tls => {
+                    match section.tls.as_ref().or(self.tls.as_ref()) {
+                        Some(tls) => check_tls(&format!("{field}.tls"), tls)?,
+                        None => {
+                            return Err(invalid(
+                                format!("{field}.tls"),
+                                "TLS input needs its own or server wide [tls] certificates",
+                            ))
+                        }
+                    }
+                    Transport::Tls(section.tls)
+                }
+                TransportKind::Tcp => Transport::Tcp,
+                TransportKind::Udp => {
+                    let rate_limit = section.rate_limit.unwrap_or_default();
+                    check_rate_limit(&format!("{field}.rate_limit"), &rate_limit)?;
+                    Transport::Udp(rate_limit)
+                }
+            };
+            inputs.push(Input {
+                bind: section.bind,
+                ipv6_only: section.ipv6_only,
+                transport,
+                protocol: section.protocol.unwrap_or_default(),
+                direction: section.direction.unwrap_or_default(),
+                lenient_xml: section.lenient_xml.unwrap_or_default(),
+            });
+        }
+
+        Ok(server::Config {
+            inputs,
+            tls: self.tls,
+            limits: self.limits,
+            outbound_queue: self.outbound_queue,
+            mesh_bridge: self.mesh_bridge,
+            timeouts: Timeouts {
+                tls_handshake: from_secs(self.timeouts.tls_handshake_secs),
+                first_event: from_secs(self.timeouts.first_event_secs),
+                idle: from_secs(self.timeouts.idle_secs),
+                ping_interval: from_secs(self.timeouts.ping_interval_secs),
+            },
+            xml_limits,
+            frame_limits,
+            shutdown_grace_period: Duration::from_secs(self.shutdown.grace_period_secs),
+        })
+    }
+}
+
+impl FromStr for FileConfig {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffered_channel::OverflowPolicy;
+    use crate::protocol::mesh::MeshEncoding;
+
+    const TEST_TLS: &str = r#"
+        [tls]
+        ca = "tests/certs/ca.crt"
+        cert = "tests/certs/server.crt"
+        key = "tests/certs/server.key"
+    "#;
+
+    fn validate(toml: &str) -> Result<server::Config, ConfigError> {
+        format!("{toml}\n{TEST_TLS}")
+            .parse::<FileConfig>()?
+            .into_server_config()
+    }
+
+    fn invalid_field(res: Result<server::Config, ConfigError>) -> String {
+        match res {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            other => panic!("expected validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_example_config_parses() -> anyhow::Result<()> {
+        let config: FileConfig = include_str!("../server.example.toml").parse()?;
+        assert_eq!(config.log_level()?, LevelFilter::INFO);
+        assert_eq!(config.inputs.len(), 3);
+        assert_eq!(
+            config.outbound_queue.overflow_policy,
+            OverflowPolicy::Disconnect { max_lagged: 1000 }
+        );
+        assert_eq!(
+            config.mesh_bridge.map(|bridge| bridge.encoding),
+            Some(MeshEncoding::Proto)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_config() -> anyhow::Result<()> {
+        let config = validate(
+            r#"
+            [[inputs]]
+            bind = "[::]:8089"
+            transport = "tls"
+            protocol = "xml"
+
+            [[inputs]]
+            bind = "127.0.0.1:8087"
+            transport = "tcp"
+            direction = "read-only"
+            lenient_xml = true
+
+            [[inputs]]
+            bind = "0.0.0.0:8087"
+            transport = "udp"
+            rate_limit = { per_second = 10.0, burst = 20 }
+
+            [limits]
+            max_per_ip = 5
+
+            [mesh_bridge]
+
+            [timeouts]
+            idle_secs = 0
+            ping_interval_secs = 60
+
+            [xml_limits]
+            max_depth = 8
+
+            [frame_limits]
+            max_proto_size = 1048576
+
+            [shutdown]
+            grace_period_secs = 3
+            "#,
+        )?;
+
+        assert_eq!(config.inputs.len(), 3);
+        assert_eq!(config.inputs[0].protocol, Protocol::Xml);
+        assert!(matches!(config.inputs[0].transport, Transport::Tls(None)));
+        assert_eq!(config.inputs[1].direction, Direction::ReadOnly);
+        assert!(config.inputs[1].lenient_xml);
+        assert!(!config.inputs[0].lenient_xml);
+        assert!(matches!(
+            config.inputs[2].transport,
+            Transport::Udp(RateLimit { burst: 20, .. })
+        ));
+        assert_eq!(config.limits.max_connections, 100);
+        assert_eq!(config.limits.max_per_ip, Some(5));
+        assert_eq!(
+            config.mesh_bridge.map(|bridge| bridge.group.to_string()),
+            Some("239.2.3.1:6969".to_string())
+        );
+        assert_eq!(config.timeouts.idle, None);
+        assert_eq!(config.timeouts.ping_interval, Some(Duration::from_secs(60)));
+        assert_eq!(config.timeouts.first_event, Timeouts::default().first_event);
+        assert_eq!(config.xml_limits.max_depth, 8);
+        assert_eq!(
+            config.xml_limits.max_elements,
+            XmlLimits::default().max_elements
+        );
+        assert_eq!(config.frame_limits.max_proto_size, 1024 * 1024);
+        assert_eq!(
+            config.frame_limits.max_xml_size,
+            FrameLimits::default().max_xml_size
+        );
+        assert_eq!(config.shutdown_grace_period, Duration::from_secs(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let cases = [
+            ("inputs = []", "inputs"),
+            (
+                "[[inputs]]\nbind = \"0.0.0.0:1\"\ntransport = \"tcp\"\nrate_limit = {}",
+                "inputs[0].rate_limit",
+            ),
+            (
+                "[[inputs]]\nbind = \"0.0.0.0:1\"\ntransport = \"udp\"\ndirection = \"write-only\"",
+                "inputs[0]",
+            ),
+            (
+                "[[inputs]]\nbind = \"0.0.0.0:1\"\ntransport = \"udp\"\nlenient_xml = true",
+                "inputs[0].lenient_xml",
+            ),
+            (
+                "[[inputs]]\nbind = \"0.0.0.0:1\"\ntransport = \"tcp\"\n[[inputs]]\nbind = \"0.0.0.0:1\"\ntransport = \"tls\"",
+                "inputs[1]",
+            ),
+            (
+                "[[inputs]]\nbind = \"0.0.0.0:1\"\ntransport = \"tls\"\ntls = { ca = \"tests/certs/ca.crt\", cert = \"missing.crt\", key = \"tests/certs/server.key\" }",
+                "inputs[0].tls.cert",
+            ),
+            ("[limits]\nmax_connections = 0", "limits.max_connections"),
+            ("[xml_limits]\nmax_text_len = 0", "xml_limits.max_text_len"),
+            ("[frame_limits]\nmax_xml_size = 0", "frame_limits.max_xml_size"),
+            ("[mesh_bridge]\ngroup = \"10.0.0.1:6969\"", "mesh_bridge.group"),
+        ];
+        for (toml, field) in cases {
+            assert_eq!(invalid_field(validate(toml)), field, "for {toml}");
+        }
+
+        let config: FileConfig = "[logging]\nlevel = \"verbose\"".parse().expect("parsed");
+        assert!(matches!(
+            config.log_level(),
+            Err(ConfigError::Invalid { field, .. }) if field == "logging.level"
+        ));
+        assert!(matches!(
+            "[limits]\nmax_clients = 1".parse::<FileConfig>(),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_default_input_is_mtls_only() -> anyhow::Result<()> {
+        let config = validate("")?;
+        assert_eq!(config.inputs.len(), 1);
+        assert!(matches!(config.inputs[0].transport, Transport::Tls(None)));
+        assert_eq!(config.inputs[0].bind, "[::]:8089".parse()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_input_without_certificates() {
+        let mut config: FileConfig = "".parse().expect("parsed");
+        config.tls = None;
+        assert_eq!(
+            invalid_field(config.into_server_config()),
+            "inputs[0].tls".to_string()
+        );
+    }
+
+    #[test]
+    fn test_input_short_form() -> anyhow::Result<()> {
+        let input: InputSection = "udp://[::]:8087".parse()?;
+        assert_eq!(input.transport, TransportKind::Udp);
+        assert_eq!(input.bind, "[::]:8087".parse()?);
+        assert!("ftp://0.0.0.0:21".parse::<InputSection>().is_err());
+        assert!("tls:8089".parse::<InputSection>().is_err());
+        Ok(())
+    }
+}
@@ -1,12 +1,24 @@
-use crate::protocol::CodecError;
-use crate::{protocol::xml::CotLegacyCodec, router::Router};
-use futures::StreamExt;
-use std::io::ErrorKind;
+use crate::buffered_channel::BufferedReceiver;
+use crate::protocol::negotiation::{self, StreamCodec, TAK_PROTO_VERSION};
+use crate::protocol::ping;
+use crate::protocol::proto::TakProtoCodec;
+use crate::protocol::xml::{CotLegacyCodec, XmlLimits};
+use crate::protocol::{CodecError, Message, SharedMessage};
+use crate::router::Router;
+use futures::{SinkExt, StreamExt};
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+use time::OffsetDateTime;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     select,
+    time::{sleep_until, timeout, timeout_at, Instant},
 };
-use tokio_util::codec::Framed;
+use tokio_util::codec::{Encoder, Framed};
+use tracing::{debug, info, warn};
+
+/// close flushes and sends TLS close_notify, peer which stopped reading would hold it forever
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 fn unexpected_eof_is_none<V>(res: Option<Result<V, CodecError>>) -> Option<Result<V, CodecError>> {
     match res {
@@ -16,66 +28,330 @@ fn unexpected_eof_is_none<V>(res: Option<Result<V, CodecError>>) -> Option<Resul
     }
 }
 
+/// which way CoT flows, from server's point of view
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    #[default]
+    ReadWrite,
+    /// server only reads, nothing is sent to the client
+    ReadOnly,
+    /// server only writes, events sent by the client are ignored
+    WriteOnly,
+}
+
+impl Direction {
+    pub fn reads(self) -> bool {
+        self != Direction::WriteOnly
+    }
+
+    pub fn writes(self) -> bool {
+        self != Direction::ReadOnly
+    }
+}
+
+/// wire protocol of a streaming connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// xml only, TAK protocol is not offered
+    Xml,
+    /// TAK protocol version 1 from the first byte, without negotiation
+    Proto,
+    /// starts with xml and lets client negotiate TAK protocol
+    #[default]
+    Auto,
+}
+
+/// how long a client may stay silent, none disables the check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// from TCP accept until TLS handshake completes
+    pub tls_handshake: Option<Duration>,
+    /// from connection setup until the first frame is read
+    pub first_event: Option<Duration>,
+    /// between two frames read from the client
+    pub idle: Option<Duration>,
+    /// server pings client silent for this long and closes connection
+    /// if nothing comes back within the same time, needs both directions
+    pub ping_interval: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            tls_handshake: Some(Duration::from_secs(10)),
+            first_event: Some(Duration::from_secs(30)),
+            idle: Some(Duration::from_secs(300)),
+            ping_interval: None,
+        }
+    }
+}
+
+/// largest frame read from a client, bigger ones are errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrameLimits {
+    /// bytes of a whole `<event>`, including anything before it
+    pub max_xml_size: usize,
+    /// bytes of `TakMessage` payload, without header
+    pub max_proto_size: usize,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self {
+            max_xml_size: 64 * 1024,
+            max_proto_size: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mode {
+    pub direction: Direction,
+    pub protocol: Protocol,
+    /// malformed xml frames are skipped instead of closing connection
+    pub lenient_xml: bool,
+    /// xml frames over them are treated as malformed
+    pub xml_limits: XmlLimits,
+    pub frame_limits: FrameLimits,
+    /// first event and idle timeouts apply only if connection reads
+    pub timeouts: Timeouts,
+}
+
 pub struct CotClientConnection<T> {
     io_stream: T,
     connection_id: String,
+    mode: Mode,
+    outbound: BufferedReceiver<SharedMessage>,
     router: Router,
 }
 
 impl<T> CotClientConnection<T> {
-    pub fn new(io_stream: T, connection_id: String, router: Router) -> Self {
+    pub fn new(
+        io_stream: T,
+        connection_id: String,
+        mode: Mode,
+        outbound: BufferedReceiver<SharedMessage>,
+        router: Router,
+    ) -> Self {
         Self {
             io_stream,
             connection_id,
+            mode,
+            outbound,
             router,
         }
     }
+
+    pub fn connection_id(&self) -> &str {
+        &self.connection_id
+    }
 }
 
 struct Defer<F>
 where
-    F: FnMut() -> (),
+    F: FnMut(),
 {
     f: F,
 }
 
 impl<F> Drop for Defer<F>
 where
-    F: FnMut() -> (),
+    F: FnMut(),
 {
     fn drop(&mut self) {
         (self.f)()
     }
 }
 
-fn defer<F: FnMut() -> ()>(f: F) -> Defer<F> {
+fn defer<F: FnMut()>(f: F) -> Defer<F> {
     Defer { f }
 }
 
-impl<T: AsyncRead + AsyncWrite> CotClientConnection<T> {
-    pub async fn conn_loop(self) -> anyhow::Result<()> {
+/// peer which stopped reading blocks a send for good and with it every timeout of the loop,
+/// so sends give up at `deadline` with [`ErrorKind::TimedOut`]
+async fn send_until<T, I>(
+    frames: &mut Framed<T, StreamCodec>,
+    item: I,
+    deadline: Option<Instant>,
+) -> Result<(), CodecError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    StreamCodec: Encoder<I, Error = CodecError>,
+{
+    let Some(deadline) = deadline else {
+        return frames.send(item).await;
+    };
+    timeout_at(deadline, frames.send(item))
+        .await
+        .map_err(|_| io::Error::new(ErrorKind::TimedOut, "peer stopped reading"))?
+}
+
+/// answers `t-x-takp-q` and switches codec to protobuf when request is accepted
+async fn negotiate<T: AsyncRead + AsyncWrite + Unpin>(
+    frames: &mut Framed<T, StreamCodec>,
+    connection_id: &str,
+    message: &Message,
+    mode: &Mode,
+    deadline: Option<Instant>,
+) -> Result<(), CodecError> {
+    let Some(version) = negotiation::requested_version(message) else {
+        debug!("Conn: {connection_id} ignoring {:?}", message.event_type());
+        return Ok(());
+    };
+    if frames.codec().is_proto() {
+        debug!("Conn: {connection_id} already uses TAK protocol");
+        return Ok(());
+    }
+
+    let accepted = version == TAK_PROTO_VERSION;
+    let response = negotiation::version_response(accepted, OffsetDateTime::now_utc());
+    send_until(frames, response, deadline).await?;
+    if accepted {
+        frames
+            .codec_mut()
+            .upgrade(mode.frame_limits.max_proto_size, mode.xml_limits);
+        info!("Conn: {connection_id} switched to TAK protocol version {version}");
+    } else {
+        info!("Conn: {connection_id} requested unsupported TAK protocol version {version}");
+    }
+    Ok(())
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> CotClientConnection<T> {
+    pub async fn conn_loop(mut self) -> anyhow::Result<()> {
         let router = self.router.clone();
         let connection_id = self.connection_id.clone();
         let _deref = defer(move || {
             router.connection_dropped(&connection_id);
         });
 
-        let frames = Framed::new(self.io_stream, CotLegacyCodec::new(4 * 1024));
-        let (mut frame_writer, mut frame_stream) = frames.split();
+        let frame_limits = self.mode.frame_limits;
+        let codec = match self.mode.protocol {
+            Protocol::Proto => StreamCodec::Proto(
+                TakProtoCodec::new(frame_limits.max_proto_size)
+                    .with_xml_limits(self.mode.xml_limits),
+            ),
+            Protocol::Xml | Protocol::Auto => {
+                let xml = if self.mode.lenient_xml {
+                    CotLegacyCodec::lenient(frame_limits.max_xml_size)
+                } else {
+                    CotLegacyCodec::new(frame_limits.max_xml_size)
+                };
+                StreamCodec::Xml(xml.with_limits(self.mode.xml_limits))
+            }
+        };
+        let mut frames = Framed::new(self.io_stream, codec);
+        let writes = self.mode.direction.writes();
+        let negotiates = writes && self.mode.protocol == Protocol::Auto;
+
+        // silent client would hold its task forever
+        let reads = self.mode.direction.reads();
+        let timeouts = self.mode.timeouts;
+        let mut silence_limit = timeouts
+            .first_event
+            .filter(|_| reads)
+            .map(|timeout| ("first event", timeout));
+        let mut last_read = Instant::now();
+        let ping_interval = timeouts.ping_interval.filter(|_| reads && writes);
+        let mut ping_sent: Option<Instant> = None;
+
+        if negotiates {
+            let support = negotiation::version_support(OffsetDateTime::now_utc());
+            let deadline = silence_limit.map(|(_, timeout)| last_read + timeout);
+            send_until(&mut frames, support, deadline).await?;
+        }
 
         loop {
+            let read_deadline = last_read + silence_limit.map_or(Duration::ZERO, |(_, t)| t);
+            let ping_deadline =
+                ping_sent.unwrap_or(last_read) + ping_interval.unwrap_or(Duration::ZERO);
+            // blocked send must not outlast the deadlines which close a dead link,
+            // pong is awaited for an interval after the ping, which is itself still due
+            let pong_deadline = ping_interval.map(|interval| match ping_sent {
+                Some(_) => ping_deadline,
+                None => ping_deadline + interval,
+            });
+            let send_deadline = [silence_limit.map(|_| read_deadline), pong_deadline]
+                .into_iter()
+                .flatten()
+                .min();
             select! {
-                maybe_frame_res = frame_stream.next() => {
+                maybe_frame_res = frames.next() => {
                     if let Some(frame_res) = unexpected_eof_is_none(maybe_frame_res) {
+                        last_read = Instant::now();
+                        ping_sent = None;
+                        silence_limit = timeouts.idle.filter(|_| reads).map(|timeout| ("event", timeout));
                         let message = frame_res?;
-                        self.router.cot_packet_received(&self.connection_id, message)?;
+                        if ping::is_ping(&message) {
+                            // keepalive is between client and server only
+                            if writes {
+                                let pong = ping::pong(OffsetDateTime::now_utc());
+                                send_until(&mut frames, pong, send_deadline).await?;
+                            }
+                        } else if ping::is_pong(&message) {
+                            debug!("Conn: {} answered ping", self.connection_id);
+                        } else if negotiation::is_negotiation(&message) {
+                            if negotiates {
+                                negotiate(&mut frames, &self.connection_id, &message, &self.mode, send_deadline).await?;
+                            }
+                        } else if reads {
+                            self.router.cot_packet_received(&self.connection_id, message)?;
+                        } else {
+                            debug!("Conn: {} is write-only, ignoring {:?}", self.connection_id, message.uid());
+                        }
                     } else {
                         break
                     }
-
+                }
+                maybe_message = self.outbound.read_next(), if writes => {
+                    if let Some(message) = maybe_message {
+                        // router keeps messages in xml form, protobuf needs them to be valid events
+                        match send_until(&mut frames, message, send_deadline).await {
+                            Err(CodecError::Validation(e)) => {
+                                warn!("Conn: {} message can not be transcoded: {e}", self.connection_id);
+                            }
+                            res => res?,
+                        }
+                    } else {
+                        info!("Outbound queue closed by router");
+                        break
+                    }
+                }
+                _ = sleep_until(read_deadline), if silence_limit.is_some() => {
+                    if let Some((awaited, timeout)) = silence_limit {
+                        warn!("Conn: {} closed, no {awaited} within {timeout:?}", self.connection_id);
+                    }
+                    break
+                }
+                _ = sleep_until(ping_deadline), if ping_interval.is_some() => {
+                    if ping_sent.is_some() {
+                        warn!(
+                            "Conn: {} closed, ping not answered within {:?}",
+                            self.connection_id,
+                            ping_interval.unwrap_or_default()
+                        );
+                        break
+                    }
+                    let ping = ping::ping(OffsetDateTime::now_utc());
+                    send_until(&mut frames, ping, send_deadline).await?;
+                    ping_sent = Some(Instant::now());
+                }
+                // writing connections finish once router closes their queue
+                _ = self.router.shutting_down(), if !writes => {
+                    info!("Conn: {} closed by server shutdown", self.connection_id);
+                    break
                 }
             }
         }
+        // clean end of stream, TLS clients get close_notify
+        match timeout(CLOSE_TIMEOUT, SinkExt::<SharedMessage>::close(&mut frames)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => debug!("Conn: {} close failed: {e}", self.connection_id),
+            Err(_) => debug!("Conn: {} close timed out", self.connection_id),
+        }
         Ok(())
     }
 }
@@ -87,7 +363,7 @@ mod test {
     use std::io::ErrorKind;
     use std::pin::Pin;
     use std::task::{Context, Poll};
-    use tokio::io::{AsyncRead, ReadBuf};
+    use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
 
     struct UnexpectedEOFReader;
     impl AsyncRead for UnexpectedEOFReader {
@@ -104,29 +380,258 @@ mod test {
         fn poll_write(
             self: Pin<&mut Self>,
             _cx: &mut Context<'_>,
-            _buf: &[u8],
+            buf: &[u8],
         ) -> Poll<Result<usize, io::Error>> {
-            unimplemented!()
+            // version advertisement goes nowhere
+            Poll::Ready(Ok(buf.len()))
         }
 
         fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-            unimplemented!()
+            Poll::Ready(Ok(()))
         }
 
         fn poll_shutdown(
             self: Pin<&mut Self>,
             _cx: &mut Context<'_>,
         ) -> Poll<Result<(), io::Error>> {
-            unimplemented!()
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn timeouts(first_event: u64, idle: u64) -> Mode {
+        Mode {
+            timeouts: Timeouts {
+                tls_handshake: None,
+                first_event: Some(Duration::from_millis(first_event)),
+                idle: Some(Duration::from_millis(idle)),
+                ping_interval: None,
+            },
+            ..Default::default()
         }
     }
 
     #[tokio::test]
     async fn test_client_disconnection_without_err() {
-        //FIXME - need a guard against infinite loop
-        let client_conn =
-            CotClientConnection::new(UnexpectedEOFReader, "test conn".into(), Router::new(1));
-        let res = client_conn.conn_loop().await;
+        let (_sender, outbound) = crate::buffered_channel::channel(1);
+        let client_conn = CotClientConnection::new(
+            UnexpectedEOFReader,
+            "test conn".into(),
+            Default::default(),
+            outbound,
+            Router::new(Default::default(), Default::default()),
+        );
+        let res = tokio::time::timeout(Duration::from_secs(1), client_conn.conn_loop())
+            .await
+            .expect("loop ends on EOF");
         assert!(res.is_ok())
     }
+
+    #[tokio::test]
+    async fn test_silent_client_is_closed() {
+        let (_sender, outbound) = crate::buffered_channel::channel(1);
+        let (client, server) = tokio::io::duplex(1024);
+        let client_conn = CotClientConnection::new(
+            server,
+            "test conn".into(),
+            timeouts(50, 1000),
+            outbound,
+            Router::new(Default::default(), Default::default()),
+        );
+        let res = tokio::time::timeout(Duration::from_secs(1), client_conn.conn_loop())
+            .await
+            .expect("first event timeout");
+        assert!(res.is_ok());
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn test_idle_client_is_closed() -> anyhow::Result<()> {
+        let (_sender, outbound) = crate::buffered_channel::channel(1);
+        let (mut client, server) = tokio::io::duplex(1024);
+        let client_conn = CotClientConnection::new(
+            server,
+            "test conn".into(),
+            timeouts(50, 200),
+            outbound,
+            Router::new(Default::default(), Default::default()),
+        );
+        let started = Instant::now();
+        let conn_task = tokio::spawn(client_conn.conn_loop());
+        client
+            .write_all(br#"<event uid="client" type="a-f-G"><detail/></event>"#)
+            .await?;
+        tokio::time::timeout(Duration::from_secs(1), conn_task).await???;
+        // first event came in time, idle timeout applies from then on
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_frame_over_configured_size_closes_connection() -> anyhow::Result<()> {
+        let (_sender, outbound) = crate::buffered_channel::channel(1);
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mode = Mode {
+            protocol: Protocol::Xml,
+            frame_limits: FrameLimits {
+                max_xml_size: 32,
+                ..Default::default()
+            },
+            ..timeouts(1000, 1000)
+        };
+        let client_conn = CotClientConnection::new(
+            server,
+            "test conn".into(),
+            mode,
+            outbound,
+            Router::new(Default::default(), Default::default()),
+        );
+        let conn_task = tokio::spawn(client_conn.conn_loop());
+        client
+            .write_all(br#"<event uid="client" type="a-f-G"><detail/></event>"#)
+            .await?;
+        let res = tokio::time::timeout(Duration::from_secs(1), conn_task).await??;
+        let err = res.expect_err("frame is too large");
+        assert!(matches!(
+            err.downcast_ref::<CodecError>(),
+            Some(CodecError::FrameTooLarge { max: 32, .. })
+        ));
+        Ok(())
+    }
+
+    /// connection whose client never reads while the outbound queue is full of SA
+    fn stuck_writer(
+        mode: Mode,
+    ) -> (
+        CotClientConnection<tokio::io::DuplexStream>,
+        tokio::io::DuplexStream,
+    ) {
+        let (sender, outbound) = crate::buffered_channel::channel(16);
+        let sa = Message::from_raw_xml(include_str!("protocol/xml/fixtures/first_event.xml"))
+            .expect("valid xml");
+        for _ in 0..16 {
+            sender.send(sa.clone().into()).expect("queued");
+        }
+        let (client, server) = tokio::io::duplex(256);
+        let client_conn = CotClientConnection::new(
+            server,
+            "test conn".into(),
+            mode,
+            outbound,
+            Router::new(Default::default(), Default::default()),
+        );
+        (client_conn, client)
+    }
+
+    fn assert_timed_out(res: anyhow::Result<()>) {
+        let err = res.expect_err("blocked send fails");
+        assert!(
+            matches!(err.downcast_ref(), Some(CodecError::Io(e)) if e.kind() == ErrorKind::TimedOut),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_which_does_not_read_is_closed_by_silence_timeout() {
+        let mode = Mode {
+            protocol: Protocol::Xml,
+            ..timeouts(100, 1000)
+        };
+        // client side stays open, but nothing is ever read from it
+        let (client_conn, _client) = stuck_writer(mode);
+        let started = Instant::now();
+        let res = tokio::time::timeout(Duration::from_secs(1), client_conn.conn_loop())
+            .await
+            .expect("send gives up at first event deadline");
+        assert_timed_out(res);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_client_which_does_not_read_is_closed_by_ping_timeout() {
+        let mode = Mode {
+            protocol: Protocol::Xml,
+            timeouts: Timeouts {
+                tls_handshake: None,
+                first_event: None,
+                idle: None,
+                ping_interval: Some(Duration::from_millis(50)),
+            },
+            ..Default::default()
+        };
+        let (client_conn, _client) = stuck_writer(mode);
+        let started = Instant::now();
+        let res = tokio::time::timeout(Duration::from_secs(1), client_conn.conn_loop())
+            .await
+            .expect("send gives up when pong is due");
+        assert_timed_out(res);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_ping_is_answered_and_not_routed() -> anyhow::Result<()> {
+        let router = Router::new(Default::default(), Default::default());
+        let mut observer = router.attach_output("observer");
+        let (_sender, outbound) = crate::buffered_channel::channel(1);
+        let (client, server) = tokio::io::duplex(4096);
+        let client_conn = CotClientConnection::new(
+            server,
+            "test conn".into(),
+            Default::default(),
+            outbound,
+            router,
+        );
+        let _conn_task = tokio::spawn(client_conn.conn_loop());
+
+        let mut client = Framed::new(
+            client,
+            StreamCodec::xml(FrameLimits::default().max_xml_size),
+        );
+        let support = client.next().await.expect("advertisement")?;
+        assert!(negotiation::is_negotiation(&support));
+        client.send(ping::ping(OffsetDateTime::now_utc())).await?;
+        let pong = client.next().await.expect("pong")?;
+        assert!(ping::is_pong(&pong));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), observer.read_next())
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_ping_closes_connection() -> anyhow::Result<()> {
+        let (_sender, outbound) = crate::buffered_channel::channel(1);
+        let (client, server) = tokio::io::duplex(4096);
+        let mode = Mode {
+            timeouts: Timeouts {
+                tls_handshake: None,
+                first_event: None,
+                idle: None,
+                ping_interval: Some(Duration::from_millis(50)),
+            },
+            ..Default::default()
+        };
+        let client_conn = CotClientConnection::new(
+            server,
+            "test conn".into(),
+            mode,
+            outbound,
+            Router::new(Default::default(), Default::default()),
+        );
+        let started = Instant::now();
+        let conn_task = tokio::spawn(client_conn.conn_loop());
+
+        let mut client = Framed::new(
+            client,
+            StreamCodec::xml(FrameLimits::default().max_xml_size),
+        );
+        let support = client.next().await.expect("advertisement")?;
+        assert!(negotiation::is_negotiation(&support));
+        let ping = client.next().await.expect("ping")?;
+        assert!(ping::is_ping(&ping));
+        tokio::time::timeout(Duration::from_secs(1), conn_task).await???;
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        Ok(())
+    }
 }
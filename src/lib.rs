@@ -1,7 +1,12 @@
 pub mod buffered_channel;
+pub mod config;
 pub mod connection;
+pub mod multicast;
 pub mod protocol;
+pub mod rate_limit;
 pub mod router;
 pub mod server;
+pub mod tcp;
 pub mod tls;
 pub mod tracing;
+pub mod udp;
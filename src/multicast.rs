@@ -0,0 +1,185 @@
+//! bridge between ATAK mesh SA on a multicast group and clients connected to the server
+
+use crate::protocol::mesh::{MeshCodec, MeshEncoding};
+use crate::protocol::xml::XmlLimits;
+use crate::rate_limit::{RateLimit, TokenBucket};
+use crate::router::Router;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::select;
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::{debug, info, warn};
+
+/// emitted datagram is expected to come back through multicast loopback within it
+const ECHO_WINDOW: Duration = Duration::from_secs(5);
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub group: SocketAddrV4,
+    /// local interface to join the group on, unspecified lets OS pick one
+    pub interface: Ipv4Addr,
+    /// encoding of re-emitted server traffic, both are accepted from the group
+    pub encoding: MeshEncoding,
+    pub rate_limit: RateLimit,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            group: SocketAddrV4::new(Ipv4Addr::new(239, 2, 3, 1), 6969),
+            interface: Ipv4Addr::UNSPECIFIED,
+            encoding: MeshEncoding::Proto,
+            rate_limit: Default::default(),
+        }
+    }
+}
+
+/// socket joined to the group, shared with other mesh applications on the same host
+pub fn bind(config: &Config) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.group.port()).into())?;
+    socket.join_multicast_v4(config.group.ip(), &config.interface)?;
+    socket.set_multicast_if_v4(&config.interface)?;
+    // local mesh applications should see bridged traffic too, own echo is filtered out
+    socket.set_multicast_loop_v4(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// hashes of datagrams sent to the group, so they are not bridged back to the server
+#[derive(Default)]
+struct EmittedPayloads {
+    hashes: VecDeque<(Instant, u64)>,
+}
+
+impl EmittedPayloads {
+    fn hash(payload: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        payload.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while matches!(self.hashes.front(), Some((emitted, _)) if now.saturating_duration_since(*emitted) > ECHO_WINDOW)
+        {
+            self.hashes.pop_front();
+        }
+    }
+
+    fn remember(&mut self, payload: &[u8], now: Instant) {
+        self.expire(now);
+        self.hashes.push_back((now, Self::hash(payload)));
+    }
+
+    /// every emitted datagram is recognised once
+    fn is_echo(&mut self, payload: &[u8], now: Instant) -> bool {
+        self.expire(now);
+        let hash = Self::hash(payload);
+        if let Some(pos) = self.hashes.iter().position(|(_, h)| *h == hash) {
+            self.hashes.remove(pos);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// forwards mesh traffic to the router and everything routed to the bridge to the group,
+/// returns only if socket fails
+pub async fn run_bridge(
+    socket: UdpSocket,
+    config: Config,
+    xml_limits: XmlLimits,
+    router: Router,
+) -> anyhow::Result<()> {
+    let bridge_id = format!("multicast-{}", config.group);
+    info!("Bridging mesh SA on: {}", config.group);
+    let res = bridge_loop(socket, config, xml_limits, &router, &bridge_id).await;
+    router.connection_dropped(&bridge_id);
+    res
+}
+
+async fn bridge_loop(
+    socket: UdpSocket,
+    config: Config,
+    xml_limits: XmlLimits,
+    router: &Router,
+    bridge_id: &String,
+) -> anyhow::Result<()> {
+    let mut outbound = router.attach_output(bridge_id);
+    let mut codec = MeshCodec::new(config.encoding).with_xml_limits(xml_limits);
+    let mut emitted = EmittedPayloads::default();
+    let mut bucket = TokenBucket::new(config.rate_limit, Instant::now());
+    let mut dropped = 0u64;
+    let mut recv_buff = vec![0u8; MAX_DATAGRAM_SIZE];
+    let mut send_buff = BytesMut::new();
+
+    loop {
+        select! {
+            res = socket.recv_from(&mut recv_buff) => {
+                let (len, sender) = res?;
+                let payload = &recv_buff[..len];
+                let now = Instant::now();
+                if emitted.is_echo(payload, now) {
+                    continue;
+                }
+                if !bucket.try_acquire(now) {
+                    dropped += 1;
+                    if dropped.is_power_of_two() {
+                        warn!("Bridge: {bridge_id} over rate limit, dropped {dropped} messages");
+                    }
+                    continue;
+                }
+                debug!("Bridge: {bridge_id} datagram from: {sender}");
+                match codec.decode(&mut BytesMut::from(payload)) {
+                    Ok(Some(message)) => router.input_packet_received(bridge_id, message),
+                    Ok(None) => {}
+                    Err(e) => warn!("Bridge: {bridge_id} invalid datagram from {sender}: {e}"),
+                }
+            }
+            maybe_message = outbound.read_next() => {
+                let Some(message) = maybe_message else {
+                    info!("Bridge: {bridge_id} detached by router");
+                    return Ok(());
+                };
+                send_buff.clear();
+                if let Err(e) = codec.encode(message, &mut send_buff) {
+                    warn!("Bridge: {bridge_id} message can not be sent to mesh: {e}");
+                    continue;
+                }
+                emitted.remember(&send_buff, Instant::now());
+                socket.send_to(&send_buff, config.group).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emitted_payload_is_recognised_once() {
+        let start = Instant::now();
+        let mut emitted = EmittedPayloads::default();
+        emitted.remember(b"first", start);
+        emitted.remember(b"second", start);
+
+        assert!(!emitted.is_echo(b"third", start));
+        assert!(emitted.is_echo(b"second", start));
+        assert!(!emitted.is_echo(b"second", start));
+
+        let later = start + ECHO_WINDOW + Duration::from_millis(1);
+        assert!(!emitted.is_echo(b"first", later));
+    }
+}
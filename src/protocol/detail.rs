@@ -0,0 +1,902 @@
+use minidom::{Element, NSChoice, Node};
+use std::collections::BTreeMap;
+
+/// well known sub-element of CoT `<detail>`
+pub trait DetailElement: Sized {
+    const NAME: &'static str;
+    /// child elements the type models, [`Detail::set`] keeps the others
+    const TYPED_CHILDREN: &'static [&'static str] = &[];
+    /// whether text content is modelled as well
+    const TYPED_TEXT: bool = false;
+
+    /// attributes the type models, [`Detail::set`] keeps the others
+    fn is_typed_attr(name: &str) -> bool;
+
+    fn from_element(elem: &Element) -> Option<Self>;
+
+    fn to_element(&self) -> Element;
+
+    /// first matching child of `<detail>` element
+    fn find_in(detail: &Element) -> Option<Self> {
+        detail
+            .get_child(Self::NAME, NSChoice::Any)
+            .and_then(Self::from_element)
+    }
+}
+
+/// free form `<detail>` content, elements without typed counterpart are kept as is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detail {
+    element: Element,
+}
+
+impl Default for Detail {
+    fn default() -> Self {
+        Self {
+            element: Element::bare("detail", ""),
+        }
+    }
+}
+
+impl Detail {
+    pub(crate) fn from_element(element: Element) -> Self {
+        Self { element }
+    }
+
+    pub fn element(&self) -> &Element {
+        &self.element
+    }
+
+    pub fn element_mut(&mut self) -> &mut Element {
+        &mut self.element
+    }
+
+    pub fn get<T: DetailElement>(&self) -> Option<T> {
+        T::find_in(&self.element)
+    }
+
+    /// every child of given kind, e.g. all `<link>`s
+    pub fn get_all<T: DetailElement>(&self) -> Vec<T> {
+        self.element
+            .children()
+            .filter(|child| child.name() == T::NAME)
+            .filter_map(T::from_element)
+            .collect()
+    }
+
+    /// updates first child of this kind in place or appends a new one,
+    /// whatever the type does not model stays as it was
+    pub fn set<T: DetailElement>(&mut self, value: &T) {
+        let mut new_child = value.to_element();
+        match self
+            .element
+            .children_mut()
+            .find(|child| child.name() == T::NAME)
+        {
+            Some(child) => {
+                keep_untyped::<T>(child, &mut new_child);
+                *child = new_child;
+            }
+            None => {
+                self.element.append_child(new_child);
+            }
+        }
+    }
+
+    /// adds another child even if one of this kind is present, e.g. second `<link>`
+    pub fn append<T: DetailElement>(&mut self, value: &T) {
+        self.element.append_child(value.to_element());
+    }
+
+    pub fn remove<T: DetailElement>(&mut self) -> Option<T> {
+        self.element
+            .remove_child(T::NAME, NSChoice::Any)
+            .and_then(|child| T::from_element(&child))
+    }
+}
+
+/// copies attributes, children and text of `old` which `T` does not model to `new`
+fn keep_untyped<T: DetailElement>(old: &Element, new: &mut Element) {
+    for (name, value) in old.attrs().filter(|(name, _)| !T::is_typed_attr(name)) {
+        new.set_attr(name, value);
+    }
+    for node in old.nodes() {
+        match node {
+            Node::Element(child) if T::TYPED_CHILDREN.contains(&child.name()) => {}
+            Node::Text(_) if T::TYPED_TEXT => {}
+            node => {
+                new.append_node(node.clone());
+            }
+        }
+    }
+}
+
+fn attr(elem: &Element, name: &str) -> Option<String> {
+    elem.attr(name).map(str::to_string)
+}
+
+fn parse_attr<T: std::str::FromStr>(elem: &Element, name: &str) -> Option<T> {
+    elem.attr(name).and_then(|value| value.trim().parse().ok())
+}
+
+/// `<contact callsign endpoint phone/>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub callsign: String,
+    pub endpoint: Option<String>,
+    pub phone: Option<String>,
+}
+
+impl DetailElement for Contact {
+    const NAME: &'static str = "contact";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "callsign" | "endpoint" | "phone")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            callsign: attr(elem, "callsign")?,
+            endpoint: attr(elem, "endpoint"),
+            phone: attr(elem, "phone"),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("callsign", self.callsign.as_str())
+            .attr("endpoint", self.endpoint.as_deref())
+            .attr("phone", self.phone.as_deref())
+            .build()
+    }
+}
+
+/// team color and role, `<__group name role/>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub role: String,
+}
+
+impl DetailElement for Group {
+    const NAME: &'static str = "__group";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "name" | "role")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            name: attr(elem, "name")?,
+            role: attr(elem, "role").unwrap_or_default(),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("name", self.name.as_str())
+            .attr("role", self.role.as_str())
+            .build()
+    }
+}
+
+/// TAK client software info, `<takv device platform os version/>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Takv {
+    pub device: Option<String>,
+    pub platform: Option<String>,
+    pub os: Option<String>,
+    pub version: Option<String>,
+}
+
+impl DetailElement for Takv {
+    const NAME: &'static str = "takv";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "device" | "platform" | "os" | "version")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            device: attr(elem, "device"),
+            platform: attr(elem, "platform"),
+            os: attr(elem, "os"),
+            version: attr(elem, "version"),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("device", self.device.as_deref())
+            .attr("platform", self.platform.as_deref())
+            .attr("os", self.os.as_deref())
+            .attr("version", self.version.as_deref())
+            .build()
+    }
+}
+
+/// movement, `<track speed course/>`, speed in m/s and course in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Track {
+    pub speed: f64,
+    pub course: f64,
+}
+
+impl DetailElement for Track {
+    const NAME: &'static str = "track";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "speed" | "course")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            speed: parse_attr(elem, "speed")?,
+            course: parse_attr(elem, "course")?,
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("speed", format!("{:?}", self.speed))
+            .attr("course", format!("{:?}", self.course))
+            .build()
+    }
+}
+
+/// `<status battery readiness/>`, battery is percents or a fraction depending on the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub battery: Option<f64>,
+    pub readiness: Option<bool>,
+}
+
+impl DetailElement for Status {
+    const NAME: &'static str = "status";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "battery" | "readiness")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            battery: parse_attr(elem, "battery"),
+            readiness: parse_attr(elem, "readiness"),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            // display formatting writes whole percents without fraction
+            .attr("battery", self.battery.map(|battery| battery.to_string()))
+            .attr(
+                "readiness",
+                self.readiness.map(|readiness| readiness.to_string()),
+            )
+            .build()
+    }
+}
+
+/// where point came from, `<precisionlocation geopointsrc altsrc/>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrecisionLocation {
+    pub geopointsrc: Option<String>,
+    pub altsrc: Option<String>,
+}
+
+impl DetailElement for PrecisionLocation {
+    const NAME: &'static str = "precisionlocation";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "geopointsrc" | "altsrc")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            geopointsrc: attr(elem, "geopointsrc"),
+            altsrc: attr(elem, "altsrc"),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("geopointsrc", self.geopointsrc.as_deref())
+            .attr("altsrc", self.altsrc.as_deref())
+            .build()
+    }
+}
+
+/// `<uid Droid/>`, device name of the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uid {
+    pub droid: String,
+}
+
+impl DetailElement for Uid {
+    const NAME: &'static str = "uid";
+
+    fn is_typed_attr(name: &str) -> bool {
+        name == "Droid"
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            droid: attr(elem, "Droid")?,
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("Droid", self.droid.as_str())
+            .build()
+    }
+}
+
+/// relation to another event, `<link uid type relation .../>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub uid: String,
+    pub link_type: Option<String>,
+    pub relation: Option<String>,
+    pub production_time: Option<String>,
+    pub parent_callsign: Option<String>,
+}
+
+impl DetailElement for Link {
+    const NAME: &'static str = "link";
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(
+            name,
+            "uid" | "type" | "relation" | "production_time" | "parent_callsign"
+        )
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            uid: attr(elem, "uid")?,
+            link_type: attr(elem, "type"),
+            relation: attr(elem, "relation"),
+            production_time: attr(elem, "production_time"),
+            parent_callsign: attr(elem, "parent_callsign"),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("uid", self.uid.as_str())
+            .attr("type", self.link_type.as_deref())
+            .attr("relation", self.relation.as_deref())
+            .attr("production_time", self.production_time.as_deref())
+            .attr("parent_callsign", self.parent_callsign.as_deref())
+            .build()
+    }
+}
+
+/// free text, `<remarks source to time>text</remarks>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remarks {
+    pub source: Option<String>,
+    pub to: Option<String>,
+    pub time: Option<String>,
+    pub text: String,
+}
+
+impl DetailElement for Remarks {
+    const NAME: &'static str = "remarks";
+    const TYPED_TEXT: bool = true;
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "source" | "to" | "time")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            source: attr(elem, "source"),
+            to: attr(elem, "to"),
+            time: attr(elem, "time"),
+            text: elem.text(),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        let mut elem = Element::builder(Self::NAME, "")
+            .attr("source", self.source.as_deref())
+            .attr("to", self.to.as_deref())
+            .attr("time", self.time.as_deref())
+            .build();
+        if !self.text.is_empty() {
+            elem.append_text_node(self.text.as_str());
+        }
+        elem
+    }
+}
+
+/// `<usericon iconsetpath/>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserIcon {
+    pub iconsetpath: String,
+}
+
+impl DetailElement for UserIcon {
+    const NAME: &'static str = "usericon";
+
+    fn is_typed_attr(name: &str) -> bool {
+        name == "iconsetpath"
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            iconsetpath: attr(elem, "iconsetpath")?,
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("iconsetpath", self.iconsetpath.as_str())
+            .build()
+    }
+}
+
+/// `<color argb/>`, signed 32 bit ARGB as java clients write it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub argb: i32,
+}
+
+impl DetailElement for Color {
+    const NAME: &'static str = "color";
+
+    fn is_typed_attr(name: &str) -> bool {
+        name == "argb"
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            argb: parse_attr(elem, "argb")?,
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("argb", self.argb)
+            .build()
+    }
+}
+
+/// alert state, `<emergency type cancel>callsign</emergency>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Emergency {
+    pub emergency_type: Option<String>,
+    pub cancel: bool,
+    pub text: String,
+}
+
+impl DetailElement for Emergency {
+    const NAME: &'static str = "emergency";
+    const TYPED_TEXT: bool = true;
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(name, "type" | "cancel")
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            emergency_type: attr(elem, "type"),
+            cancel: parse_attr(elem, "cancel").unwrap_or_default(),
+            text: elem.text(),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        let mut elem = Element::builder(Self::NAME, "")
+            .attr("type", self.emergency_type.as_deref())
+            .attr("cancel", self.cancel.then_some("true"))
+            .build();
+        if !self.text.is_empty() {
+            elem.append_text_node(self.text.as_str());
+        }
+        elem
+    }
+}
+
+/// chat participants, `<chatgrp uid0 uid1 ... id/>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatGroup {
+    pub id: Option<String>,
+    pub uids: Vec<String>,
+}
+
+impl DetailElement for ChatGroup {
+    const NAME: &'static str = "chatgrp";
+
+    fn is_typed_attr(name: &str) -> bool {
+        name == "id"
+            || name
+                .strip_prefix("uid")
+                .is_some_and(|index| index.parse::<usize>().is_ok())
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        let uids = (0..)
+            .map_while(|index| elem.attr(&format!("uid{index}")).map(str::to_string))
+            .collect();
+        Some(Self {
+            id: attr(elem, "id"),
+            uids,
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        let mut builder = Element::builder(Self::NAME, "").attr("id", self.id.as_deref());
+        for (index, uid) in self.uids.iter().enumerate() {
+            builder = builder.attr(format!("uid{index}"), uid.as_str());
+        }
+        builder.build()
+    }
+}
+
+/// GeoChat message header, `<__chat ...><chatgrp .../></__chat>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chat {
+    pub id: Option<String>,
+    pub chatroom: Option<String>,
+    pub sender_callsign: Option<String>,
+    pub message_id: Option<String>,
+    pub parent: Option<String>,
+    pub group_owner: Option<bool>,
+    pub chat_group: Option<ChatGroup>,
+}
+
+impl DetailElement for Chat {
+    const NAME: &'static str = "__chat";
+    const TYPED_CHILDREN: &'static [&'static str] = &["chatgrp"];
+
+    fn is_typed_attr(name: &str) -> bool {
+        matches!(
+            name,
+            "id" | "chatroom" | "senderCallsign" | "messageId" | "parent" | "groupOwner"
+        )
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            id: attr(elem, "id"),
+            chatroom: attr(elem, "chatroom"),
+            sender_callsign: attr(elem, "senderCallsign"),
+            message_id: attr(elem, "messageId"),
+            parent: attr(elem, "parent"),
+            group_owner: parse_attr(elem, "groupOwner"),
+            chat_group: ChatGroup::find_in(elem),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        let mut elem = Element::builder(Self::NAME, "")
+            .attr("id", self.id.as_deref())
+            .attr("chatroom", self.chatroom.as_deref())
+            .attr("senderCallsign", self.sender_callsign.as_deref())
+            .attr("messageId", self.message_id.as_deref())
+            .attr("parent", self.parent.as_deref())
+            .attr(
+                "groupOwner",
+                self.group_owner.map(|group_owner| group_owner.to_string()),
+            )
+            .build();
+        if let Some(chat_group) = &self.chat_group {
+            elem.append_child(chat_group.to_element());
+        }
+        elem
+    }
+}
+
+/// servers the event passed through, `<_flow-tags_ server-name="time"/>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowTags {
+    pub tags: BTreeMap<String, String>,
+}
+
+impl DetailElement for FlowTags {
+    const NAME: &'static str = "_flow-tags_";
+
+    fn is_typed_attr(_name: &str) -> bool {
+        true
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            tags: elem
+                .attrs()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        let mut elem = Element::bare(Self::NAME, "");
+        for (name, value) in &self.tags {
+            elem.set_attr(name.as_str(), value.as_str());
+        }
+        elem
+    }
+}
+
+/// where sender expects the event to be delivered, `host:port:proto:uid` entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerDestination {
+    pub destinations: Vec<String>,
+}
+
+impl DetailElement for ServerDestination {
+    const NAME: &'static str = "__serverdestination";
+
+    fn is_typed_attr(name: &str) -> bool {
+        name == "destinations"
+    }
+
+    fn from_element(elem: &Element) -> Option<Self> {
+        Some(Self {
+            destinations: elem
+                .attr("destinations")?
+                .split(',')
+                .filter(|destination| !destination.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+
+    fn to_element(&self) -> Element {
+        Element::builder(Self::NAME, "")
+            .attr("destinations", self.destinations.join(","))
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::Message;
+
+    fn fixture_detail(xml: &str) -> Detail {
+        let message = Message::from_raw_xml(xml).expect("valid xml");
+        message
+            .to_event()
+            .expect("valid event")
+            .detail
+            .expect("detail present")
+    }
+
+    fn assert_round_trip<T: DetailElement + PartialEq + std::fmt::Debug>(value: &T) {
+        assert_eq!(T::from_element(&value.to_element()).as_ref(), Some(value));
+    }
+
+    #[test]
+    fn test_self_sa_details() {
+        let detail = fixture_detail(include_str!("xml/fixtures/first_event.xml"));
+
+        let contact = detail.get::<Contact>().expect("contact");
+        assert_eq!(contact.callsign, "Aaaaa");
+        assert_eq!(contact.endpoint.as_deref(), Some("*:-1:stcp"));
+        assert_eq!(contact.phone.as_deref(), Some(""));
+        assert_round_trip(&contact);
+
+        let group = detail.get::<Group>().expect("group");
+        assert_eq!(
+            group,
+            Group {
+                name: "Cyan".to_string(),
+                role: "Team Member".to_string()
+            }
+        );
+        assert_round_trip(&group);
+
+        let takv = detail.get::<Takv>().expect("takv");
+        assert_eq!(takv.platform.as_deref(), Some("iTAK"));
+        assert_eq!(takv.version.as_deref(), Some("1.1.1.666"));
+        assert_round_trip(&takv);
+
+        let track = detail.get::<Track>().expect("track");
+        assert_eq!(track.speed, 1.1166956424713135);
+        assert_eq!(track.course, 347.9174499511719);
+        assert_round_trip(&track);
+
+        let status = detail.get::<Status>().expect("status");
+        assert_eq!(status.battery, Some(70.0));
+        assert_eq!(status.to_element().attr("battery"), Some("70"));
+        assert_round_trip(&status);
+
+        let precision = detail.get::<PrecisionLocation>().expect("precision");
+        assert_eq!(precision.geopointsrc.as_deref(), Some("GPS"));
+        assert_round_trip(&precision);
+
+        let uid = detail.get::<Uid>().expect("uid");
+        assert_eq!(uid.droid, "Aaaaa");
+        assert_round_trip(&uid);
+    }
+
+    #[test]
+    fn test_alert_details() {
+        let detail = fixture_detail(include_str!("xml/fixtures/911_alert_start.xml"));
+
+        let emergency = detail.get::<Emergency>().expect("emergency");
+        assert_eq!(
+            emergency,
+            Emergency {
+                emergency_type: Some("Alert".to_string()),
+                cancel: false,
+                text: "Aaaaa".to_string()
+            }
+        );
+        assert_round_trip(&emergency);
+
+        let link = detail.get::<Link>().expect("link");
+        assert_eq!(link.uid, "F6FD50A3-2827-4651-AFCC-2257F36B4C96");
+        assert_eq!(link.relation.as_deref(), Some("p-p"));
+        assert_eq!(link.parent_callsign.as_deref(), Some("Aaaaa"));
+        assert_round_trip(&link);
+
+        let icon = detail.get::<UserIcon>().expect("usericon");
+        assert_eq!(icon.iconsetpath, "911 Alert");
+        assert_round_trip(&icon);
+
+        let color = detail.get::<Color>().expect("color");
+        assert_eq!(color.argb, -1);
+        assert_round_trip(&color);
+
+        let status = detail.get::<Status>().expect("status");
+        assert_eq!(status.readiness, Some(true));
+        assert_eq!(status.battery, Some(0.65));
+
+        let remarks = detail.get::<Remarks>().expect("remarks");
+        assert_eq!(remarks.text, "");
+
+        let cancel = fixture_detail(include_str!("xml/fixtures/911_deactive.xml"));
+        let emergency = cancel.get::<Emergency>().expect("emergency");
+        assert!(emergency.cancel);
+        assert_round_trip(&emergency);
+    }
+
+    #[test]
+    fn test_chat_details() {
+        let detail = fixture_detail(include_str!("xml/fixtures/general_chat_message.xml"));
+
+        let chat = detail.get::<Chat>().expect("chat");
+        assert_eq!(chat.chatroom.as_deref(), Some("All Chat Rooms"));
+        assert_eq!(chat.sender_callsign.as_deref(), Some("Aaaaa"));
+        assert_eq!(chat.group_owner, Some(false));
+        assert_eq!(
+            chat.chat_group,
+            Some(ChatGroup {
+                id: Some("All Chat Rooms".to_string()),
+                uids: vec![
+                    "F6FD50A3-2827-4651-AFCC-2257F36B4C96".to_string(),
+                    "All Chat Rooms".to_string()
+                ],
+            })
+        );
+        assert_round_trip(&chat);
+
+        let remarks = detail.get::<Remarks>().expect("remarks");
+        assert_eq!(remarks.to.as_deref(), Some("All Chat Rooms"));
+        assert_eq!(remarks.text.trim(), "Testing");
+        assert_round_trip(&remarks);
+
+        let destination = detail
+            .get::<ServerDestination>()
+            .expect("server destination");
+        assert_eq!(
+            destination.destinations,
+            vec!["1.1.1.1:6666:tcp:F6FD50A3-2827-4651-AFCC-2257F36B4C96".to_string()]
+        );
+        assert_round_trip(&destination);
+
+        let flow_tags = detail.get::<FlowTags>().expect("flow tags");
+        assert_eq!(
+            flow_tags
+                .tags
+                .get("TAK-Server-dd4055d128d5416e826423948c66e412")
+                .map(String::as_str),
+            Some("2023-12-23T19:25:49Z")
+        );
+        assert_round_trip(&flow_tags);
+    }
+
+    #[test]
+    fn test_unknown_elements_are_preserved() {
+        let mut detail = fixture_detail(include_str!("xml/fixtures/contact_alert.xml"));
+        let names = |detail: &Detail| {
+            detail
+                .element()
+                .children()
+                .map(|child| child.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        let before = names(&detail);
+        let archive = detail
+            .element()
+            .get_child("archive", NSChoice::Any)
+            .cloned();
+
+        let mut contact = detail.get::<Contact>().expect("contact");
+        contact.callsign = "Bbbbb".to_string();
+        detail.set(&contact);
+        detail.set(&Track {
+            speed: 1.0,
+            course: 90.0,
+        });
+        detail.append(&Link {
+            uid: "other".to_string(),
+            link_type: None,
+            relation: Some("p-p".to_string()),
+            production_time: None,
+            parent_callsign: None,
+        });
+
+        let mut expected = before;
+        expected.extend(["track".to_string(), "link".to_string()]);
+        assert_eq!(names(&detail), expected);
+        assert_eq!(
+            detail
+                .element()
+                .get_child("archive", NSChoice::Any)
+                .cloned(),
+            archive
+        );
+        assert_eq!(
+            detail.get::<Contact>().map(|c| c.callsign).as_deref(),
+            Some("Bbbbb")
+        );
+        assert_eq!(detail.get_all::<Link>().len(), 2);
+
+        assert!(detail.remove::<Track>().is_some());
+        assert!(detail.get::<Track>().is_none());
+    }
+
+    #[test]
+    fn test_set_keeps_untyped_content() -> anyhow::Result<()> {
+        let message = Message::from_raw_xml(concat!(
+            "<event><detail>",
+            r#"<contact callsign="Aaaaa" phone="123" emailAddress="a@b.c"><extra x="1"/></contact>"#,
+            r#"<remarks source="A">old<mention uid="B"/></remarks>"#,
+            r#"<__chat id="All Chat Rooms" chatroom="All Chat Rooms" custom="kept">"#,
+            r#"<chatgrp uid0="A" id="All Chat Rooms"/><hierarchy/></__chat>"#,
+            "</detail></event>",
+        ))?;
+        let mut detail = Detail::from_element(message.detail().expect("detail").clone());
+
+        detail.set(&Contact {
+            callsign: "Bbbbb".to_string(),
+            endpoint: Some("*:-1:stcp".to_string()),
+            phone: None,
+        });
+        detail.set(&Remarks {
+            source: None,
+            to: None,
+            time: None,
+            text: "new".to_string(),
+        });
+        let mut chat = detail.get::<Chat>().expect("chat");
+        chat.sender_callsign = Some("Bbbbb".to_string());
+        chat.chat_group = None;
+        detail.set(&chat);
+
+        let expected = Message::from_raw_xml(concat!(
+            "<event><detail>",
+            r#"<contact callsign="Bbbbb" endpoint="*:-1:stcp" emailAddress="a@b.c"><extra x="1"/></contact>"#,
+            r#"<remarks>new<mention uid="B"/></remarks>"#,
+            r#"<__chat id="All Chat Rooms" chatroom="All Chat Rooms" senderCallsign="Bbbbb" custom="kept">"#,
+            "<hierarchy/></__chat>",
+            "</detail></event>",
+        ))?;
+        let render = |elem: &Element| {
+            let mut xml = Vec::new();
+            elem.write_to(&mut xml).map(|_| String::from_utf8(xml))
+        };
+        // element equality stops at the shorter list of children, rendered xml does not
+        assert_eq!(
+            render(detail.element())??,
+            render(expected.detail().expect("detail"))??
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,472 @@
+use super::detail::Detail;
+use minidom::{Element, Node};
+use std::collections::BTreeMap;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// reasons why xml element can not be treated as a CoT event
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ValidationError {
+    #[error("expected <event>, got <{0}>")]
+    NotAnEvent(String),
+    #[error("missing <{0}> element")]
+    MissingElement(&'static str),
+    #[error("<{element}> is missing {attr} attribute")]
+    MissingAttribute {
+        element: &'static str,
+        attr: &'static str,
+    },
+    #[error("<{element}> has invalid {attr}: {value}")]
+    InvalidAttribute {
+        element: &'static str,
+        attr: &'static str,
+        value: String,
+    },
+}
+
+const EVENT_ATTRS: [&str; 9] = [
+    "version", "uid", "type", "how", "time", "start", "stale", "access", "qos",
+];
+const POINT_ATTRS: [&str; 5] = ["lat", "lon", "hae", "ce", "le"];
+
+/// attribute values as they were parsed, rendered instead of the typed field
+/// as long as they still parse to its value; equality ignores them
+#[derive(Debug, Clone, Default)]
+pub struct SourceAttrs(BTreeMap<&'static str, String>);
+
+impl SourceAttrs {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+impl PartialEq for SourceAttrs {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// child of `<event>`, point and detail stand for the typed fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventChild {
+    Point,
+    Detail,
+    Other(Node),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+    /// height above ellipsoid, meters
+    pub hae: f64,
+    /// circular error, meters
+    pub ce: f64,
+    /// linear error, meters
+    pub le: f64,
+    /// point attributes not covered by fields above
+    pub other_attrs: BTreeMap<String, String>,
+    pub source: SourceAttrs,
+}
+
+impl Point {
+    /// point which is nowhere in particular, used by non-spatial events like chats and pings
+    pub fn unknown() -> Self {
+        Self {
+            lat: 0.0,
+            lon: 0.0,
+            hae: 9999999.0,
+            ce: 9999999.0,
+            le: 9999999.0,
+            other_attrs: Default::default(),
+            source: Default::default(),
+        }
+    }
+}
+
+/// typed view of CoT `<event>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub version: String,
+    pub uid: String,
+    pub event_type: String,
+    pub how: String,
+    pub time: OffsetDateTime,
+    pub start: OffsetDateTime,
+    pub stale: OffsetDateTime,
+    pub access: Option<String>,
+    pub qos: Option<String>,
+    pub point: Point,
+    pub detail: Option<Detail>,
+    /// event attributes not covered by fields above
+    pub other_attrs: BTreeMap<String, String>,
+    /// every child in document order, including whitespace; point and detail which
+    /// are not listed are rendered first and last
+    pub children: Vec<EventChild>,
+    pub source: SourceAttrs,
+}
+
+fn required_attr<'a>(
+    elem: &'a Element,
+    element: &'static str,
+    attr: &'static str,
+) -> Result<&'a str, ValidationError> {
+    elem.attr(attr)
+        .ok_or(ValidationError::MissingAttribute { element, attr })
+}
+
+fn time_attr(elem: &Element, attr: &'static str) -> Result<OffsetDateTime, ValidationError> {
+    let value = required_attr(elem, "event", attr)?;
+    OffsetDateTime::parse(value, &Rfc3339).map_err(|_| ValidationError::InvalidAttribute {
+        element: "event",
+        attr,
+        value: value.to_string(),
+    })
+}
+
+fn number_attr(elem: &Element, attr: &'static str) -> Result<f64, ValidationError> {
+    let value = required_attr(elem, "point", attr)?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ValidationError::InvalidAttribute {
+            element: "point",
+            attr,
+            value: value.to_string(),
+        })
+}
+
+fn other_attrs(elem: &Element, known: &[&str]) -> BTreeMap<String, String> {
+    elem.attrs()
+        .filter(|(name, _)| !known.contains(name))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn source_attrs(elem: &Element, known: &[&'static str]) -> SourceAttrs {
+    SourceAttrs(
+        known
+            .iter()
+            .filter_map(|&name| Some((name, elem.attr(name)?.to_string())))
+            .collect(),
+    )
+}
+
+fn format_time(time: OffsetDateTime, source: Option<&str>) -> String {
+    match source {
+        Some(value) if OffsetDateTime::parse(value, &Rfc3339) == Ok(time) => value.to_string(),
+        _ => time
+            .format(&Rfc3339)
+            .expect("time should be formattable as rfc3339"),
+    }
+}
+
+// debug formatting keeps `.0` for whole numbers, same as TAK clients send them
+fn format_number(value: f64, source: Option<&str>) -> String {
+    match source {
+        Some(source) if source.trim().parse() == Ok(value) => source.to_string(),
+        _ => format!("{value:?}"),
+    }
+}
+
+impl TryFrom<&Element> for Event {
+    type Error = ValidationError;
+
+    fn try_from(elem: &Element) -> Result<Self, Self::Error> {
+        if elem.name() != "event" {
+            return Err(ValidationError::NotAnEvent(elem.name().to_string()));
+        }
+
+        let version = required_attr(elem, "event", "version")?.to_string();
+        let uid = required_attr(elem, "event", "uid")?.to_string();
+        let event_type = required_attr(elem, "event", "type")?.to_string();
+        let how = required_attr(elem, "event", "how")?.to_string();
+        let time = time_attr(elem, "time")?;
+        let start = time_attr(elem, "start")?;
+        let stale = time_attr(elem, "stale")?;
+
+        // first point and detail are the typed ones, anything else is kept as it is
+        let mut point = None;
+        let mut detail = None;
+        let children = elem
+            .nodes()
+            .map(|node| match node {
+                Node::Element(child) if child.name() == "point" && point.is_none() => {
+                    point = Some(child);
+                    EventChild::Point
+                }
+                Node::Element(child) if child.name() == "detail" && detail.is_none() => {
+                    detail = Some(Detail::from_element(child.clone()));
+                    EventChild::Detail
+                }
+                node => EventChild::Other(node.clone()),
+            })
+            .collect();
+        let point = point.ok_or(ValidationError::MissingElement("point"))?;
+
+        Ok(Self {
+            version,
+            uid,
+            event_type,
+            how,
+            time,
+            start,
+            stale,
+            access: elem.attr("access").map(str::to_string),
+            qos: elem.attr("qos").map(str::to_string),
+            point: Point {
+                lat: number_attr(point, "lat")?,
+                lon: number_attr(point, "lon")?,
+                hae: number_attr(point, "hae")?,
+                ce: number_attr(point, "ce")?,
+                le: number_attr(point, "le")?,
+                other_attrs: other_attrs(point, &POINT_ATTRS),
+                source: source_attrs(point, &POINT_ATTRS),
+            },
+            detail,
+            other_attrs: other_attrs(elem, &EVENT_ATTRS),
+            children,
+            source: source_attrs(elem, &["time", "start", "stale"]),
+        })
+    }
+}
+
+impl From<&Event> for Element {
+    fn from(event: &Event) -> Self {
+        let mut builder = Element::builder("event", "")
+            .attr("version", event.version.as_str())
+            .attr("uid", event.uid.as_str())
+            .attr("type", event.event_type.as_str())
+            .attr("how", event.how.as_str())
+            .attr("time", format_time(event.time, event.source.get("time")))
+            .attr("start", format_time(event.start, event.source.get("start")))
+            .attr("stale", format_time(event.stale, event.source.get("stale")))
+            .attr("access", event.access.as_deref())
+            .attr("qos", event.qos.as_deref());
+        for (name, value) in &event.other_attrs {
+            builder = builder.attr(name.as_str(), value.as_str());
+        }
+
+        let point = &event.point;
+        let mut point_builder = Element::builder("point", "");
+        for (name, value) in POINT_ATTRS
+            .into_iter()
+            .zip([point.lat, point.lon, point.hae, point.ce, point.le])
+        {
+            point_builder = point_builder.attr(name, format_number(value, point.source.get(name)));
+        }
+        for (name, value) in &point.other_attrs {
+            point_builder = point_builder.attr(name.as_str(), value.as_str());
+        }
+        let point = point_builder.build();
+        let detail = event.detail.as_ref().map(|detail| detail.element().clone());
+
+        if !event.children.contains(&EventChild::Point) {
+            builder = builder.append(point.clone());
+        }
+        for child in &event.children {
+            builder = match child {
+                EventChild::Point => builder.append(point.clone()),
+                EventChild::Detail => builder.append_all(detail.clone()),
+                EventChild::Other(node) => builder.append(node.clone()),
+            };
+        }
+        if !event.children.contains(&EventChild::Detail) {
+            builder = builder.append_all(detail);
+        }
+        builder.build()
+    }
+}
+
+impl From<Event> for Element {
+    fn from(event: Event) -> Self {
+        (&event).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::Message;
+    use minidom::NSChoice;
+    use time::macros::datetime;
+
+    fn parse(xml: &str) -> Element {
+        Element::from_reader_with_prefixes(xml.as_bytes(), Some("".to_string())).expect("valid xml")
+    }
+
+    #[test]
+    fn test_event_fields() {
+        let elem = parse(include_str!("xml/fixtures/additional.xml"));
+        let event = Event::try_from(&elem).expect("valid event");
+        assert_eq!(event.version, "2.0");
+        assert_eq!(event.uid, "F6FD50A3-2827-4651-AFCC-2257F36B4C96");
+        assert_eq!(event.event_type, "a-f-G-E-V-C");
+        assert_eq!(event.how, "m-g");
+        assert_eq!(event.time, datetime!(2023-12-23 19:24:05 UTC));
+        assert_eq!(event.start, datetime!(2023-12-23 19:24:05 UTC));
+        assert_eq!(event.stale, datetime!(2023-12-23 19:26:05 UTC));
+        assert_eq!(event.access, None);
+        assert_eq!(
+            event.point,
+            Point {
+                lat: 11.22315966042454,
+                lon: 11.225991481255767,
+                hae: 0.00777356488825,
+                ce: 9999999.0,
+                le: 9999999.0,
+                other_attrs: Default::default(),
+                source: Default::default(),
+            }
+        );
+        let detail = event.detail.expect("detail present");
+        assert!(detail.element().has_child("takv", NSChoice::Any));
+    }
+
+    #[test]
+    fn test_fixtures_round_trip() {
+        let fixtures = [
+            include_str!("xml/fixtures/first_event.xml"),
+            include_str!("xml/fixtures/additional.xml"),
+            include_str!("xml/fixtures/911_alert_start.xml"),
+            include_str!("xml/fixtures/911_deactive.xml"),
+            include_str!("xml/fixtures/contact_alert.xml"),
+            include_str!("xml/fixtures/general_chat_message.xml"),
+        ];
+
+        for fixture in fixtures {
+            let elem = parse(fixture);
+            let event = Event::try_from(&elem).expect("valid event");
+            let rendered = Element::from(&event);
+            assert_eq!(rendered, elem);
+            assert_eq!(to_xml(&rendered), to_xml(&elem));
+            assert_eq!(Event::try_from(&rendered), Ok(event));
+        }
+    }
+
+    fn to_xml(elem: &Element) -> String {
+        let mut xml = Vec::new();
+        elem.write_to(&mut xml).expect("renderable");
+        String::from_utf8(xml).expect("utf-8")
+    }
+
+    const SOURCE_FORMS: &str = concat!(
+        r#"<event version="2.0" uid="u" type="a-u-G" how="h-e" time="2023-12-23T19:24:05.100Z" "#,
+        r#"start="2023-12-23T19:24:05.100Z" stale="2023-12-23T19:26:05Z">"#,
+        r#"<extra/> <point lat="1" lon="2.50" hae="0" ce="9999999" le="9999999"/> <detail/></event>"#,
+    );
+
+    #[test]
+    fn test_source_forms_and_child_order_are_kept() {
+        let elem = parse(SOURCE_FORMS);
+        let event = Event::try_from(&elem).expect("valid event");
+        assert_eq!(to_xml(&Element::from(&event)), to_xml(&elem));
+    }
+
+    #[test]
+    fn test_modified_fields_are_rendered_again() {
+        let mut event = Event::try_from(&parse(SOURCE_FORMS)).expect("valid event");
+        event.point.lat = 1.5;
+        event.stale += time::Duration::minutes(1);
+        let rendered = to_xml(&Element::from(&event));
+        assert!(rendered.contains(r#"lat="1.5""#), "{rendered}");
+        assert!(rendered.contains(r#"lon="2.50""#), "{rendered}");
+        assert!(
+            rendered.contains(r#"stale="2023-12-23T19:27:05Z""#),
+            "{rendered}"
+        );
+        assert!(
+            rendered.contains(r#"time="2023-12-23T19:24:05.100Z""#),
+            "{rendered}"
+        );
+        assert!(rendered.contains(r#"<extra/> <point "#), "{rendered}");
+    }
+
+    #[test]
+    fn test_unknown_attributes_and_children_are_kept() {
+        let elem = parse(concat!(
+            r#"<event version="2.0" uid="u" type="a-u-G" how="h-e" time="2023-12-23T19:24:05.123Z" "#,
+            r#"start="2023-12-23T19:24:05Z" stale="2023-12-23T19:26:05Z" access="Unclassified" opex="e">"#,
+            r#"<point lat="1.5" lon="2.0" hae="0.0" ce="1.0" le="1.0" src="gps"/>"#,
+            r#"<detail><remarks>hi</remarks></detail>"#,
+            r#"<flow-tags marti="2023-12-23T19:24:05Z"/><extra a="b">text<nested/></extra>"#,
+            r#"</event>"#,
+        ));
+        let event = Event::try_from(&elem).expect("valid event");
+        assert_eq!(event.access.as_deref(), Some("Unclassified"));
+        assert_eq!(event.other_attrs.get("opex").map(String::as_str), Some("e"));
+        assert_eq!(
+            event.point.other_attrs.get("src").map(String::as_str),
+            Some("gps")
+        );
+        assert_eq!(
+            event
+                .children
+                .iter()
+                .filter(|child| matches!(child, EventChild::Other(_)))
+                .count(),
+            2
+        );
+
+        let rendered = Element::from(&event);
+        assert_eq!(rendered, elem);
+        // element equality stops at the shorter list of children, rendered xml does not
+        assert_eq!(to_xml(&rendered), to_xml(&elem));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let cases = [
+            ("<point/>", ValidationError::NotAnEvent("point".to_string())),
+            (
+                r#"<event version="2.0" uid="u" type="t" how="h" time="2023-12-23T19:24:05Z" start="2023-12-23T19:24:05Z" stale="2023-12-23T19:24:05Z"/>"#,
+                ValidationError::MissingElement("point"),
+            ),
+            (
+                r#"<event version="2.0" type="t"/>"#,
+                ValidationError::MissingAttribute {
+                    element: "event",
+                    attr: "uid",
+                },
+            ),
+            (
+                r#"<event version="2.0" uid="u" type="t" how="h" time="yesterday" start="2023-12-23T19:24:05Z" stale="2023-12-23T19:24:05Z"/>"#,
+                ValidationError::InvalidAttribute {
+                    element: "event",
+                    attr: "time",
+                    value: "yesterday".to_string(),
+                },
+            ),
+            (
+                r#"<event version="2.0" uid="u" type="t" how="h" time="2023-12-23T19:24:05Z" start="2023-12-23T19:24:05Z" stale="2023-12-23T19:24:05Z"><point lat="north" lon="0" hae="0" ce="0" le="0"/></event>"#,
+                ValidationError::InvalidAttribute {
+                    element: "point",
+                    attr: "lat",
+                    value: "north".to_string(),
+                },
+            ),
+        ];
+
+        for (xml, expected) in cases {
+            assert_eq!(Event::try_from(&parse(xml)), Err(expected), "for {xml}");
+        }
+    }
+
+    #[test]
+    fn test_message_conversion() -> anyhow::Result<()> {
+        let message = Message::from_raw_xml(include_str!("xml/fixtures/first_event.xml"))?;
+        let event = message.to_event()?;
+        assert_eq!(event.uid, "F6FD50A3-2827-4651-AFCC-2257F36B4C96");
+        assert_eq!(
+            Message::from(event).to_event()?.point.hae,
+            155.9209900023327
+        );
+
+        let not_event = Message::from_raw_xml("<event><abc/></event>")?;
+        assert!(matches!(
+            not_event.to_event(),
+            Err(crate::protocol::CodecError::Validation(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! UDP datagrams of ATAK mesh networking, one message per datagram
+//!
+//! legacy mesh sends bare xml, TAK protocol version 1 prefixes protobuf payload with `0xBF 0x01 0xBF`
+
+use super::negotiation::TAK_PROTO_VERSION;
+use super::proto::{TakMessage, TAK_PROTO_MAGIC};
+use super::xml::{xml_parse, XmlLimits};
+use super::{CodecError, Message, SharedMessage};
+use prost::Message as _;
+use tokio_util::bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+pub const MESH_HEADER: [u8; 3] = [TAK_PROTO_MAGIC, TAK_PROTO_VERSION as u8, TAK_PROTO_MAGIC];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MeshEncoding {
+    Xml,
+    Proto,
+}
+
+/// decodes both kinds of datagrams, encodes with configured one
+pub struct MeshCodec {
+    encoding: MeshEncoding,
+    buff: Vec<u8>,
+    xml_limits: XmlLimits,
+}
+
+impl MeshCodec {
+    pub fn new(encoding: MeshEncoding) -> Self {
+        Self {
+            encoding,
+            buff: Vec::new(),
+            xml_limits: Default::default(),
+        }
+    }
+
+    /// xml datagrams and `xmlDetail` of protobuf ones over `limits` are rejected without being parsed
+    pub fn with_xml_limits(self, xml_limits: XmlLimits) -> Self {
+        Self { xml_limits, ..self }
+    }
+}
+
+fn decode_proto(datagram: &[u8], xml_limits: &XmlLimits) -> Result<Option<Message>, CodecError> {
+    if datagram[1] != TAK_PROTO_VERSION as u8 {
+        return Err(CodecError::UnsupportedVersion(datagram[1]));
+    }
+    if datagram[2] != TAK_PROTO_MAGIC {
+        return Err(CodecError::InvalidMagicByte(datagram[2]));
+    }
+    Message::from_tak_message(
+        TakMessage::decode(&datagram[MESH_HEADER.len()..])?,
+        xml_limits,
+    )
+}
+
+impl Decoder for MeshCodec {
+    type Item = Message;
+    type Error = CodecError;
+
+    /// whole datagram is consumed, control only protobuf gives nothing
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        let datagram = src.split();
+        let start = datagram
+            .iter()
+            .position(|byte| !byte.is_ascii_whitespace())
+            .unwrap_or_default();
+        match datagram[start] {
+            b'<' => {
+                self.xml_limits.check(&datagram)?;
+                Ok(Some(Message::Xml(
+                    xml_parse(&datagram).map_err(CodecError::XmlParse)?,
+                )))
+            }
+            TAK_PROTO_MAGIC if datagram.len() >= MESH_HEADER.len() => {
+                decode_proto(&datagram, &self.xml_limits)
+            }
+            TAK_PROTO_MAGIC => Err(CodecError::ProtoDecode(prost::DecodeError::new(
+                "truncated mesh header",
+            ))),
+            byte => Err(CodecError::InvalidMagicByte(byte)),
+        }
+    }
+}
+
+impl Encoder<Message> for MeshCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self.encoding {
+            MeshEncoding::Xml => {
+                self.buff.clear();
+                item.as_xml(&mut self.buff)?;
+                dst.extend_from_slice(&self.buff);
+            }
+            MeshEncoding::Proto => {
+                let tak_message = item.to_tak_message()?;
+                dst.reserve(MESH_HEADER.len() + tak_message.encoded_len());
+                dst.put_slice(&MESH_HEADER);
+                tak_message
+                    .encode(dst)
+                    .expect("buffer has reserved capacity");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encoder<SharedMessage> for MeshCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: SharedMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self.encoding {
+            MeshEncoding::Xml => dst.extend_from_slice(item.xml()?),
+            MeshEncoding::Proto => {
+                let payload = item.proto_payload()?;
+                dst.reserve(MESH_HEADER.len() + payload.len());
+                dst.put_slice(&MESH_HEADER);
+                dst.put_slice(payload);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::proto::TakControl;
+    use crate::protocol::xml::LimitViolation;
+
+    const SA: &str = include_str!("xml/fixtures/first_event.xml");
+
+    #[test]
+    fn test_both_encodings_decode_to_same_message() -> anyhow::Result<()> {
+        let sa = Message::from_raw_xml(SA)?;
+        for encoding in [MeshEncoding::Xml, MeshEncoding::Proto] {
+            let mut datagram = BytesMut::new();
+            MeshCodec::new(encoding).encode(sa.clone(), &mut datagram)?;
+            assert_eq!(
+                datagram.starts_with(&MESH_HEADER),
+                encoding == MeshEncoding::Proto
+            );
+
+            let mut codec = MeshCodec::new(MeshEncoding::Xml);
+            let decoded = codec.decode(&mut datagram)?.expect("message decoded");
+            assert!(datagram.is_empty());
+            assert_eq!(decoded.uid(), sa.uid());
+            assert_eq!(decoded.to_event()?.stale, sa.to_event()?.stale);
+            assert!(codec.decode(&mut datagram)?.is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_xml_datagram_over_limits() {
+        let mut codec = MeshCodec::new(MeshEncoding::Xml).with_xml_limits(XmlLimits {
+            max_elements: 1,
+            ..Default::default()
+        });
+        let mut datagram = BytesMut::from(SA.as_bytes());
+        assert!(matches!(
+            codec.decode(&mut datagram),
+            Err(CodecError::XmlLimit(_))
+        ));
+        assert!(datagram.is_empty());
+    }
+
+    #[test]
+    fn test_proto_datagram_with_xml_detail_over_limits() -> anyhow::Result<()> {
+        let mut tak_message = Message::from_raw_xml(SA)?.to_tak_message()?;
+        if let Some(container) = tak_message
+            .cot_event
+            .as_mut()
+            .and_then(|cot_event| cot_event.detail.as_mut())
+        {
+            container.xml_detail = format!("{}{}", "<a>".repeat(32), "</a>".repeat(32));
+        }
+        let mut datagram = BytesMut::from(&MESH_HEADER[..]);
+        tak_message.encode(&mut datagram)?;
+        let mut codec = MeshCodec::new(MeshEncoding::Proto).with_xml_limits(Default::default());
+        assert!(matches!(
+            codec.decode(&mut datagram),
+            Err(CodecError::XmlLimit(LimitViolation::Depth(16)))
+        ));
+        assert!(datagram.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_xml_datagram() -> anyhow::Result<()> {
+        let mut datagram = BytesMut::from(SA.as_bytes());
+        let decoded = MeshCodec::new(MeshEncoding::Proto)
+            .decode(&mut datagram)?
+            .expect("message decoded");
+        assert_eq!(decoded.event_type(), Some("a-f-G-E-V-C"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_control_only_datagram_is_skipped() -> anyhow::Result<()> {
+        let control = TakMessage {
+            tak_control: Some(TakControl {
+                min_proto_version: 1,
+                max_proto_version: 1,
+                contact_uid: "ANDROID-1".to_string(),
+            }),
+            cot_event: None,
+        };
+        let mut datagram = BytesMut::from(&MESH_HEADER[..]);
+        control.encode(&mut datagram)?;
+        assert!(MeshCodec::new(MeshEncoding::Proto)
+            .decode(&mut datagram)?
+            .is_none());
+        assert!(datagram.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_datagrams() {
+        let mut codec = MeshCodec::new(MeshEncoding::Proto);
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&b"hello"[..])),
+            Err(CodecError::InvalidMagicByte(b'h'))
+        ));
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&[0xBF, 0x02, 0xBF, 0x00][..])),
+            Err(CodecError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&[0xBF, 0x01, 0x00][..])),
+            Err(CodecError::InvalidMagicByte(0))
+        ));
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&[0xBF, 0x01][..])),
+            Err(CodecError::ProtoDecode(_))
+        ));
+    }
+}
@@ -1,21 +1,63 @@
+use minidom::{Element, NSChoice};
 use std::io::Write;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
 
+pub mod detail;
+pub mod event;
+pub mod mesh;
+pub mod negotiation;
+pub mod ping;
+pub mod proto;
+pub mod shared;
 pub mod xml;
 
+pub use detail::{Detail, DetailElement};
+pub use event::{Event, Point, ValidationError};
+pub use shared::SharedMessage;
+
 #[derive(Debug, thiserror::Error)]
 pub enum CodecError {
     #[error("io: {0}")]
     Io(#[from] std::io::Error),
     #[error("xml parse: {0}")]
     XmlParse(minidom::Error),
+    #[error("xml framing: {0}")]
+    XmlFraming(String),
+    /// event is over configured limits or declares a document type, see [`xml::XmlLimits`]
+    #[error("xml limit: {0}")]
+    XmlLimit(#[from] xml::LimitViolation),
     #[error("xml render: {0}")]
     XmlRender(minidom::Error),
+    #[error("invalid event: {0}")]
+    Validation(#[from] ValidationError),
+    #[error("proto decode: {0}")]
+    ProtoDecode(#[from] prost::DecodeError),
+    #[error("invalid magic byte: {0:#04x}")]
+    InvalidMagicByte(u8),
+    #[error("unsupported TAK protocol version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("frame of {size} bytes exceeds limit of {max}")]
+    FrameTooLarge { size: usize, max: usize },
 }
 
-/// main Cot message, for legacy protocol should be convertable to xml
-/// for version 1 - to special Cot PROTO message (not avaialble yet
+impl From<Event> for Message {
+    fn from(event: Event) -> Self {
+        Message::Xml(event.into())
+    }
+}
+
+/// delivery target taken from `<marti><dest .../></marti>` detail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Callsign(String),
+    Uid(String),
+}
 
-#[derive(Debug)]
+/// main Cot message, for legacy protocol should be convertable to xml
+/// for version 1 - to `TakMessage` protobuf, see [`proto`]
+/// router only ever sees xml form, so clients of both protocols can talk to each other
+#[derive(Debug, Clone)]
 pub enum Message {
     Xml(minidom::Element),
 }
@@ -33,4 +75,140 @@ impl Message {
             .map(Self::Xml)
             .map_err(CodecError::XmlParse)
     }
+
+    /// server generated `t-x-d-d` event telling clients to remove `uid` from their maps
+    pub fn delete_event(uid: &str, now: OffsetDateTime) -> Message {
+        let mut detail = Detail::default();
+        detail.set(&detail::Link {
+            uid: uid.to_string(),
+            link_type: Some("a-f-G-U-C".to_string()),
+            relation: Some("p-p".to_string()),
+            production_time: None,
+            parent_callsign: None,
+        });
+        detail
+            .element_mut()
+            .append_child(Element::bare("__forcedelete", ""));
+
+        Event {
+            version: "2.0".to_string(),
+            uid: format!("{uid}-delete"),
+            event_type: "t-x-d-d".to_string(),
+            how: "h-g-i-g-o".to_string(),
+            time: now,
+            start: now,
+            stale: now + Duration::seconds(20),
+            access: None,
+            qos: None,
+            point: Point::unknown(),
+            detail: Some(detail),
+            other_attrs: Default::default(),
+            children: Default::default(),
+            source: Default::default(),
+        }
+        .into()
+    }
+
+    /// typed view of the message, fails if required CoT fields are missing or malformed
+    pub fn to_event(&self) -> Result<Event, CodecError> {
+        match self {
+            Message::Xml(elem) => Ok(Event::try_from(elem)?),
+        }
+    }
+
+    pub fn uid(&self) -> Option<&str> {
+        match self {
+            Message::Xml(elem) => elem.attr("uid"),
+        }
+    }
+
+    pub fn event_type(&self) -> Option<&str> {
+        match self {
+            Message::Xml(elem) => elem.attr("type"),
+        }
+    }
+
+    /// point in time after which event should not be displayed anymore
+    pub fn stale(&self) -> Option<OffsetDateTime> {
+        let stale = match self {
+            Message::Xml(elem) => elem.attr("stale")?,
+        };
+        OffsetDateTime::parse(stale, &Rfc3339).ok()
+    }
+
+    pub fn detail(&self) -> Option<&Element> {
+        match self {
+            Message::Xml(elem) => elem.get_child("detail", NSChoice::Any),
+        }
+    }
+
+    /// explicit destinations, empty when message should be broadcasted
+    pub fn destinations(&self) -> Vec<Destination> {
+        let Some(marti) = self
+            .detail()
+            .and_then(|detail| detail.get_child("marti", NSChoice::Any))
+        else {
+            return vec![];
+        };
+        marti
+            .children()
+            .filter(|child| child.name() == "dest")
+            .filter_map(|dest| {
+                if let Some(callsign) = dest.attr("callsign") {
+                    Some(Destination::Callsign(callsign.to_string()))
+                } else {
+                    dest.attr("uid")
+                        .map(|uid| Destination::Uid(uid.to_string()))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_marti_destinations() -> anyhow::Result<()> {
+        let message = Message::from_raw_xml(
+            r#"<event uid="abc"><detail><marti><dest callsign="Bravo"/><dest uid="ANDROID-1"/></marti></detail></event>"#,
+        )?;
+        assert_eq!(
+            message.destinations(),
+            vec![
+                Destination::Callsign("Bravo".to_string()),
+                Destination::Uid("ANDROID-1".to_string())
+            ]
+        );
+
+        let message =
+            Message::from_raw_xml(r#"<event uid="abc"><detail><marti></marti></detail></event>"#)?;
+        assert!(message.destinations().is_empty());
+
+        let message = Message::from_raw_xml(r#"<event uid="abc"><detail/></event>"#)?;
+        assert!(message.destinations().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_event() -> anyhow::Result<()> {
+        let message = Message::delete_event(
+            "ANDROID-1",
+            time::macros::datetime!(2023-12-23 19:25:49 UTC),
+        );
+        let mut xml = Vec::new();
+        message.as_xml(&mut xml)?;
+        assert_eq!(
+            String::from_utf8(xml)?,
+            concat!(
+                r#"<event how="h-g-i-g-o" stale="2023-12-23T19:26:09Z" start="2023-12-23T19:25:49Z" "#,
+                r#"time="2023-12-23T19:25:49Z" type="t-x-d-d" uid="ANDROID-1-delete" version="2.0">"#,
+                r#"<point ce="9999999.0" hae="9999999.0" lat="0.0" le="9999999.0" lon="0.0"/>"#,
+                r#"<detail><link relation="p-p" type="a-f-G-U-C" uid="ANDROID-1"/><__forcedelete/></detail>"#,
+                "</event>"
+            )
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,277 @@
+//! switching a streaming connection from xml to TAK protocol version 1
+//!
+//! server advertises supported versions with `t-x-takp-v`, client asks for one with `t-x-takp-q`
+//! and server answers `t-x-takp-r`, both sides use protobuf framing right after the answer
+
+use super::proto::TakProtoCodec;
+use super::xml::{CotLegacyCodec, XmlLimits};
+use super::{CodecError, Detail, Event, Message, Point, SharedMessage};
+use minidom::{Element, NSChoice};
+use time::{Duration, OffsetDateTime};
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// the only protobuf version there is
+pub const TAK_PROTO_VERSION: u32 = 1;
+
+pub const VERSION_SUPPORT_TYPE: &str = "t-x-takp-v";
+pub const VERSION_REQUEST_TYPE: &str = "t-x-takp-q";
+pub const VERSION_RESPONSE_TYPE: &str = "t-x-takp-r";
+
+fn control_event(event_type: &str, control: Element, now: OffsetDateTime) -> Message {
+    let mut detail = Detail::default();
+    detail
+        .element_mut()
+        .append_child(Element::builder("TakControl", "").append(control).build());
+    Event {
+        version: "2.0".to_string(),
+        uid: "protouid".to_string(),
+        event_type: event_type.to_string(),
+        how: "m-g".to_string(),
+        time: now,
+        start: now,
+        stale: now + Duration::minutes(1),
+        access: None,
+        qos: None,
+        point: Point::unknown(),
+        detail: Some(detail),
+        other_attrs: Default::default(),
+        children: Default::default(),
+        source: Default::default(),
+    }
+    .into()
+}
+
+fn control_child<'a>(message: &'a Message, event_type: &str, name: &str) -> Option<&'a Element> {
+    if message.event_type() != Some(event_type) {
+        return None;
+    }
+    message
+        .detail()?
+        .get_child("TakControl", NSChoice::Any)?
+        .get_child(name, NSChoice::Any)
+}
+
+/// `t-x-takp-v` sent by server right after client connects
+pub fn version_support(now: OffsetDateTime) -> Message {
+    control_event(
+        VERSION_SUPPORT_TYPE,
+        Element::builder("TakProtocolSupport", "")
+            .attr("version", TAK_PROTO_VERSION.to_string())
+            .build(),
+        now,
+    )
+}
+
+/// `t-x-takp-q` sent by client which wants to switch
+pub fn version_request(version: u32, now: OffsetDateTime) -> Message {
+    control_event(
+        VERSION_REQUEST_TYPE,
+        Element::builder("TakRequest", "")
+            .attr("version", version.to_string())
+            .build(),
+        now,
+    )
+}
+
+/// `t-x-takp-r` telling client whether its request was accepted
+pub fn version_response(accepted: bool, now: OffsetDateTime) -> Message {
+    control_event(
+        VERSION_RESPONSE_TYPE,
+        Element::builder("TakResponse", "")
+            .attr("status", accepted.to_string())
+            .build(),
+        now,
+    )
+}
+
+/// protocol version asked for by `t-x-takp-q`, zero if it is missing or garbled
+pub fn requested_version(message: &Message) -> Option<u32> {
+    let request = control_child(message, VERSION_REQUEST_TYPE, "TakRequest")?;
+    Some(
+        request
+            .attr("version")
+            .and_then(|version| version.parse().ok())
+            .unwrap_or_default(),
+    )
+}
+
+/// status of `t-x-takp-r`, none for any other message
+pub fn response_status(message: &Message) -> Option<bool> {
+    let response = control_child(message, VERSION_RESPONSE_TYPE, "TakResponse")?;
+    Some(response.attr("status") == Some("true"))
+}
+
+/// negotiation events are between client and server only, never routed
+pub fn is_negotiation(message: &Message) -> bool {
+    matches!(
+        message.event_type(),
+        Some(VERSION_SUPPORT_TYPE | VERSION_REQUEST_TYPE | VERSION_RESPONSE_TYPE)
+    )
+}
+
+/// codec of a streaming connection, starts as xml and may be upgraded to protobuf
+pub enum StreamCodec {
+    Xml(CotLegacyCodec),
+    Proto(TakProtoCodec),
+}
+
+impl StreamCodec {
+    pub fn xml(max_frame_size: usize) -> Self {
+        Self::Xml(CotLegacyCodec::new(max_frame_size))
+    }
+
+    /// xml which skips malformed frames, see [`CotLegacyCodec::lenient`]
+    pub fn lenient_xml(max_frame_size: usize) -> Self {
+        Self::Xml(CotLegacyCodec::lenient(max_frame_size))
+    }
+
+    pub fn proto(max_frame_size: usize) -> Self {
+        Self::Proto(TakProtoCodec::new(max_frame_size))
+    }
+
+    /// switches to protobuf framing, bytes already buffered are decoded with the new codec
+    pub fn upgrade(&mut self, max_frame_size: usize, xml_limits: XmlLimits) {
+        *self = Self::Proto(TakProtoCodec::new(max_frame_size).with_xml_limits(xml_limits));
+    }
+
+    pub fn is_proto(&self) -> bool {
+        matches!(self, Self::Proto(_))
+    }
+}
+
+impl Decoder for StreamCodec {
+    type Item = Message;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            Self::Xml(codec) => codec.decode(src),
+            Self::Proto(codec) => codec.decode(src),
+        }
+    }
+}
+
+impl Encoder<Message> for StreamCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self {
+            Self::Xml(codec) => codec.encode(item, dst),
+            Self::Proto(codec) => codec.encode(item, dst),
+        }
+    }
+}
+
+impl Encoder<SharedMessage> for StreamCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: SharedMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self {
+            Self::Xml(codec) => codec.encode(item, dst),
+            Self::Proto(codec) => codec.encode(item, dst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::proto::TAK_PROTO_MAGIC;
+    use crate::protocol::xml::LimitViolation;
+    use prost::Message as _;
+    use time::macros::datetime;
+    use tokio_util::bytes::BufMut;
+
+    #[test]
+    fn test_control_messages() -> anyhow::Result<()> {
+        let now = datetime!(2023-12-23 19:25:49 UTC);
+        let support = version_support(now);
+        assert_eq!(support.event_type(), Some(VERSION_SUPPORT_TYPE));
+        assert!(is_negotiation(&support));
+        assert_eq!(requested_version(&support), None);
+
+        let request = version_request(TAK_PROTO_VERSION, now);
+        assert_eq!(requested_version(&request), Some(TAK_PROTO_VERSION));
+
+        let garbled = Message::from_raw_xml(
+            r#"<event type="t-x-takp-q"><detail><TakControl><TakRequest/></TakControl></detail></event>"#,
+        )?;
+        assert_eq!(requested_version(&garbled), Some(0));
+
+        assert_eq!(response_status(&version_response(true, now)), Some(true));
+        assert_eq!(response_status(&version_response(false, now)), Some(false));
+        assert_eq!(response_status(&request), None);
+
+        let sa = Message::from_raw_xml(include_str!("xml/fixtures/first_event.xml"))?;
+        assert!(!is_negotiation(&sa));
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_keeps_buffered_bytes() -> anyhow::Result<()> {
+        let sa = Message::from_raw_xml(include_str!("xml/fixtures/additional.xml"))?;
+        let mut proto = TakProtoCodec::new(4 * 1024);
+        let mut buffer = BytesMut::new();
+        StreamCodec::xml(1024).encode(
+            version_request(TAK_PROTO_VERSION, OffsetDateTime::now_utc()),
+            &mut buffer,
+        )?;
+        proto.encode(sa.clone(), &mut buffer)?;
+
+        let mut codec = StreamCodec::xml(1024);
+        let request = codec.decode(&mut buffer)?.expect("request decoded");
+        assert_eq!(requested_version(&request), Some(TAK_PROTO_VERSION));
+
+        codec.upgrade(4 * 1024, Default::default());
+        assert!(codec.is_proto());
+        let decoded = codec.decode(&mut buffer)?.expect("sa decoded");
+        assert_eq!(decoded.uid(), sa.uid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_keeps_xml_limits() -> anyhow::Result<()> {
+        let sa = Message::from_raw_xml(include_str!("xml/fixtures/additional.xml"))?;
+        let mut tak_message = sa.to_tak_message()?;
+        if let Some(container) = tak_message
+            .cot_event
+            .as_mut()
+            .and_then(|cot_event| cot_event.detail.as_mut())
+        {
+            container.xml_detail = "<a><a><a></a></a></a>".to_string();
+        }
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(TAK_PROTO_MAGIC);
+        prost::encoding::encode_varint(tak_message.encoded_len() as u64, &mut buffer);
+        tak_message.encode(&mut buffer)?;
+
+        let mut codec = StreamCodec::xml(1024);
+        codec.upgrade(
+            4 * 1024,
+            XmlLimits {
+                max_depth: 3,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(CodecError::XmlLimit(LimitViolation::Depth(3)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_frames_match_encoded_message() -> anyhow::Result<()> {
+        let sa = Message::from_raw_xml(include_str!("xml/fixtures/first_event.xml"))?;
+        let shared = SharedMessage::new(sa.clone());
+        for mut codec in [StreamCodec::xml(1024), StreamCodec::proto(1024)] {
+            let mut encoded = BytesMut::new();
+            codec.encode(sa.clone(), &mut encoded)?;
+            let mut copied = BytesMut::new();
+            codec.encode(shared.clone(), &mut copied)?;
+            assert_eq!(copied, encoded);
+        }
+        Ok(())
+    }
+}
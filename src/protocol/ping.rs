@@ -0,0 +1,76 @@
+//! keepalive between client and server, `t-x-c-t` ping is answered with `t-x-c-t-r` pong
+
+use super::{Event, Message, Point};
+use time::{Duration, OffsetDateTime};
+
+pub const PING_TYPE: &str = "t-x-c-t";
+pub const PONG_TYPE: &str = "t-x-c-t-r";
+
+fn keepalive_event(uid: &str, event_type: &str, now: OffsetDateTime) -> Message {
+    Event {
+        version: "2.0".to_string(),
+        uid: uid.to_string(),
+        event_type: event_type.to_string(),
+        how: "h-g-i-g-o".to_string(),
+        time: now,
+        start: now,
+        stale: now + Duration::seconds(20),
+        access: None,
+        qos: None,
+        point: Point::unknown(),
+        detail: Some(Default::default()),
+        other_attrs: Default::default(),
+        children: Default::default(),
+        source: Default::default(),
+    }
+    .into()
+}
+
+/// sent by server to check that client is still there
+pub fn ping(now: OffsetDateTime) -> Message {
+    keepalive_event("takPing", PING_TYPE, now)
+}
+
+/// answer to client ping
+pub fn pong(now: OffsetDateTime) -> Message {
+    keepalive_event("takPong", PONG_TYPE, now)
+}
+
+pub fn is_ping(message: &Message) -> bool {
+    message.event_type() == Some(PING_TYPE)
+}
+
+pub fn is_pong(message: &Message) -> bool {
+    message.event_type() == Some(PONG_TYPE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::proto::TakProtoCodec;
+    use time::macros::datetime;
+    use tokio_util::bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_keepalive_messages() -> anyhow::Result<()> {
+        let now = datetime!(2023-12-23 19:25:49 UTC);
+        assert!(is_ping(&ping(now)));
+        assert!(is_pong(&pong(now)));
+        assert!(!is_ping(&pong(now)));
+
+        let atak_ping = Message::from_raw_xml(
+            r#"<event version="2.0" uid="ANDROID-1-ping" type="t-x-c-t" how="m-g" time="2023-12-23T19:25:49Z" start="2023-12-23T19:25:49Z" stale="2023-12-23T19:26:09Z"><point lat="0.0" lon="0.0" hae="0.0" ce="9999999.0" le="9999999.0"/><detail/></event>"#,
+        )?;
+        assert!(is_ping(&atak_ping));
+        assert!(!is_pong(&atak_ping));
+
+        // has to survive transcoding for protobuf clients
+        let mut codec = TakProtoCodec::new(1024);
+        let mut buffer = BytesMut::new();
+        codec.encode(pong(now), &mut buffer)?;
+        let decoded = codec.decode(&mut buffer)?.expect("pong decoded");
+        assert!(is_pong(&decoded));
+        Ok(())
+    }
+}
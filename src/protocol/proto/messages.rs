@@ -0,0 +1,124 @@
+//! TAK protocol version 1 messages, mirrors `takmessage.proto` and friends from ATAK sources
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TakMessage {
+    #[prost(message, optional, tag = "1")]
+    pub tak_control: Option<TakControl>,
+    #[prost(message, optional, tag = "2")]
+    pub cot_event: Option<CotEvent>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TakControl {
+    #[prost(uint32, tag = "1")]
+    pub min_proto_version: u32,
+    #[prost(uint32, tag = "2")]
+    pub max_proto_version: u32,
+    #[prost(string, tag = "3")]
+    pub contact_uid: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CotEvent {
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(string, tag = "2")]
+    pub access: String,
+    #[prost(string, tag = "3")]
+    pub qos: String,
+    #[prost(string, tag = "4")]
+    pub opex: String,
+    #[prost(string, tag = "5")]
+    pub uid: String,
+    /// milliseconds since unix epoch
+    #[prost(uint64, tag = "6")]
+    pub send_time: u64,
+    #[prost(uint64, tag = "7")]
+    pub start_time: u64,
+    #[prost(uint64, tag = "8")]
+    pub stale_time: u64,
+    #[prost(string, tag = "9")]
+    pub how: String,
+    #[prost(double, tag = "10")]
+    pub lat: f64,
+    #[prost(double, tag = "11")]
+    pub lon: f64,
+    #[prost(double, tag = "12")]
+    pub hae: f64,
+    #[prost(double, tag = "13")]
+    pub ce: f64,
+    #[prost(double, tag = "14")]
+    pub le: f64,
+    #[prost(message, optional, tag = "15")]
+    pub detail: Option<DetailContainer>,
+}
+
+/// `Detail` message of the proto definition, renamed to not clash with xml detail
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DetailContainer {
+    /// detail elements without dedicated field below, rendered as xml without `<detail>` wrapper
+    #[prost(string, tag = "1")]
+    pub xml_detail: String,
+    #[prost(message, optional, tag = "2")]
+    pub contact: Option<Contact>,
+    #[prost(message, optional, tag = "3")]
+    pub group: Option<Group>,
+    #[prost(message, optional, tag = "4")]
+    pub precision_location: Option<PrecisionLocation>,
+    #[prost(message, optional, tag = "5")]
+    pub status: Option<Status>,
+    #[prost(message, optional, tag = "6")]
+    pub takv: Option<Takv>,
+    #[prost(message, optional, tag = "7")]
+    pub track: Option<Track>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Contact {
+    #[prost(string, tag = "1")]
+    pub endpoint: String,
+    #[prost(string, tag = "2")]
+    pub callsign: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Group {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub role: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PrecisionLocation {
+    #[prost(string, tag = "1")]
+    pub geopointsrc: String,
+    #[prost(string, tag = "2")]
+    pub altsrc: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Status {
+    #[prost(uint32, tag = "1")]
+    pub battery: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Takv {
+    #[prost(string, tag = "1")]
+    pub device: String,
+    #[prost(string, tag = "2")]
+    pub platform: String,
+    #[prost(string, tag = "3")]
+    pub os: String,
+    #[prost(string, tag = "4")]
+    pub version: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Track {
+    #[prost(double, tag = "1")]
+    pub speed: f64,
+    #[prost(double, tag = "2")]
+    pub course: f64,
+}
@@ -0,0 +1,569 @@
+use crate::protocol::detail::{self, DetailElement};
+use crate::protocol::xml::XmlLimits;
+use crate::protocol::{CodecError, Detail, Event, Message, Point, SharedMessage, ValidationError};
+use minidom::Element;
+use prost::Message as _;
+use time::OffsetDateTime;
+use tokio_util::bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::debug;
+
+pub mod messages;
+
+pub use messages::{CotEvent, DetailContainer, TakControl, TakMessage};
+
+/// every TAK protocol frame starts with it
+pub const TAK_PROTO_MAGIC: u8 = 0xBF;
+
+/// varint is at most 10 bytes long
+const MAX_VARINT_LEN: usize = 10;
+
+/// streaming TAK protocol version 1: magic byte, varint payload length, `TakMessage`
+pub struct TakProtoCodec {
+    max_frame_size: usize,
+    xml_limits: XmlLimits,
+}
+
+impl TakProtoCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            xml_limits: Default::default(),
+        }
+    }
+
+    /// events with `xmlDetail` over `xml_limits` are rejected without it being parsed
+    pub fn with_xml_limits(self, xml_limits: XmlLimits) -> Self {
+        Self { xml_limits, ..self }
+    }
+}
+
+/// returns header length and payload length, none if more bytes are needed
+fn decode_header(src: &[u8]) -> Result<Option<(usize, usize)>, CodecError> {
+    let Some(&magic) = src.first() else {
+        return Ok(None);
+    };
+    if magic != TAK_PROTO_MAGIC {
+        return Err(CodecError::InvalidMagicByte(magic));
+    }
+
+    let mut payload_len = 0u64;
+    for (index, byte) in src[1..].iter().take(MAX_VARINT_LEN).enumerate() {
+        payload_len |= u64::from(byte & 0x7F) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok(Some((index + 2, payload_len as usize)));
+        }
+    }
+
+    if src.len() > MAX_VARINT_LEN {
+        Err(CodecError::ProtoDecode(prost::DecodeError::new(
+            "invalid varint",
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+impl Decoder for TakProtoCodec {
+    type Item = Message;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let Some((header_len, payload_len)) = decode_header(src)? else {
+                return Ok(None);
+            };
+            if payload_len > self.max_frame_size {
+                return Err(CodecError::FrameTooLarge {
+                    size: payload_len,
+                    max: self.max_frame_size,
+                });
+            }
+
+            let frame_len = header_len + payload_len;
+            if src.len() < frame_len {
+                src.reserve(frame_len - src.len());
+                return Ok(None);
+            }
+
+            src.advance(header_len);
+            let payload = src.split_to(payload_len);
+            let tak_message = TakMessage::decode(payload.freeze())?;
+            if let Some(message) = Message::from_tak_message(tak_message, &self.xml_limits)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+}
+
+/// magic byte and payload length, room for the payload is reserved as well
+fn put_header(payload_len: usize, dst: &mut BytesMut) {
+    dst.reserve(1 + MAX_VARINT_LEN + payload_len);
+    dst.put_u8(TAK_PROTO_MAGIC);
+    prost::encoding::encode_varint(payload_len as u64, dst);
+}
+
+impl Encoder<Message> for TakProtoCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let tak_message = item.to_tak_message()?;
+        put_header(tak_message.encoded_len(), dst);
+        tak_message
+            .encode(dst)
+            .expect("buffer has reserved capacity");
+        Ok(())
+    }
+}
+
+impl Encoder<SharedMessage> for TakProtoCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, item: SharedMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let payload = item.proto_payload()?;
+        put_header(payload.len(), dst);
+        dst.put_slice(payload);
+        Ok(())
+    }
+}
+
+impl Message {
+    /// events are converted to xml form, control only messages give nothing;
+    /// `xmlDetail` is checked against `xml_limits` before it is parsed
+    pub fn from_tak_message(
+        tak_message: TakMessage,
+        xml_limits: &XmlLimits,
+    ) -> Result<Option<Message>, CodecError> {
+        if let Some(control) = tak_message.tak_control {
+            debug!("TAK control: {control:?}");
+        }
+        tak_message
+            .cot_event
+            .map(|cot_event| Ok(Event::from_cot_event(&cot_event, xml_limits)?.into()))
+            .transpose()
+    }
+
+    pub fn to_tak_message(&self) -> Result<TakMessage, CodecError> {
+        Ok(TakMessage {
+            tak_control: None,
+            cot_event: Some((&self.to_event()?).into()),
+        })
+    }
+}
+
+fn to_millis(time: OffsetDateTime) -> u64 {
+    (time.unix_timestamp_nanos() / 1_000_000).max(0) as u64
+}
+
+fn from_millis(millis: u64, attr: &'static str) -> Result<OffsetDateTime, ValidationError> {
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(millis) * 1_000_000).map_err(|_| {
+        ValidationError::InvalidAttribute {
+            element: "event",
+            attr,
+            value: millis.to_string(),
+        }
+    })
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// element can be carried by dedicated proto message only if it has all of `required`
+/// and nothing beyond `required` and `optional` attributes
+fn fits_into(elem: &Element, required: &[&str], optional: &[&str]) -> bool {
+    elem.nodes().next().is_none()
+        && required.iter().all(|name| elem.attr(name).is_some())
+        && elem
+            .attrs()
+            .all(|(name, _)| required.contains(&name) || optional.contains(&name))
+}
+
+impl From<&Element> for DetailContainer {
+    fn from(detail: &Element) -> Self {
+        let mut container = DetailContainer::default();
+        let attr = |elem: &Element, name| elem.attr(name).unwrap_or_default().to_string();
+        for child in detail.children() {
+            match child.name() {
+                "contact"
+                    if container.contact.is_none()
+                        && fits_into(child, &["callsign"], &["endpoint"]) =>
+                {
+                    container.contact = Some(messages::Contact {
+                        endpoint: attr(child, "endpoint"),
+                        callsign: attr(child, "callsign"),
+                    });
+                }
+                "__group"
+                    if container.group.is_none() && fits_into(child, &["name", "role"], &[]) =>
+                {
+                    container.group = Some(messages::Group {
+                        name: attr(child, "name"),
+                        role: attr(child, "role"),
+                    });
+                }
+                "precisionlocation"
+                    if container.precision_location.is_none()
+                        && fits_into(child, &[], &["geopointsrc", "altsrc"]) =>
+                {
+                    container.precision_location = Some(messages::PrecisionLocation {
+                        geopointsrc: attr(child, "geopointsrc"),
+                        altsrc: attr(child, "altsrc"),
+                    });
+                }
+                "status" if container.status.is_none() && fits_into(child, &["battery"], &[]) => {
+                    if let Some(battery) = child.attr("battery").and_then(|b| b.parse().ok()) {
+                        container.status = Some(messages::Status { battery });
+                    } else {
+                        append_xml(&mut container.xml_detail, child);
+                    }
+                }
+                "takv"
+                    if container.takv.is_none()
+                        && fits_into(child, &[], &["device", "platform", "os", "version"]) =>
+                {
+                    container.takv = Some(messages::Takv {
+                        device: attr(child, "device"),
+                        platform: attr(child, "platform"),
+                        os: attr(child, "os"),
+                        version: attr(child, "version"),
+                    });
+                }
+                "track"
+                    if container.track.is_none() && fits_into(child, &["speed", "course"], &[]) =>
+                {
+                    if let Some(track) = detail::Track::from_element(child) {
+                        container.track = Some(messages::Track {
+                            speed: track.speed,
+                            course: track.course,
+                        });
+                    } else {
+                        append_xml(&mut container.xml_detail, child);
+                    }
+                }
+                _ => append_xml(&mut container.xml_detail, child),
+            }
+        }
+        container
+    }
+}
+
+fn append_xml(xml_detail: &mut String, elem: &Element) {
+    let mut buff = Vec::new();
+    elem.write_to(&mut buff)
+        .expect("parsed element should be renderable");
+    xml_detail.push_str(&String::from_utf8_lossy(&buff));
+}
+
+impl DetailContainer {
+    /// `<detail>` with typed fields first, followed by children of `xmlDetail`
+    pub fn to_element(&self, xml_limits: &XmlLimits) -> Result<Element, CodecError> {
+        let mut detail = Element::bare("detail", "");
+        if let Some(contact) = &self.contact {
+            let mut elem = Element::bare(detail::Contact::NAME, "");
+            elem.set_attr("endpoint", non_empty(&contact.endpoint));
+            elem.set_attr("callsign", contact.callsign.as_str());
+            detail.append_child(elem);
+        }
+        if let Some(group) = &self.group {
+            detail.append_child(
+                detail::Group {
+                    name: group.name.clone(),
+                    role: group.role.clone(),
+                }
+                .to_element(),
+            );
+        }
+        if let Some(precision_location) = &self.precision_location {
+            detail.append_child(
+                detail::PrecisionLocation {
+                    geopointsrc: non_empty(&precision_location.geopointsrc),
+                    altsrc: non_empty(&precision_location.altsrc),
+                }
+                .to_element(),
+            );
+        }
+        if let Some(status) = &self.status {
+            detail.append_child(
+                detail::Status {
+                    battery: Some(f64::from(status.battery)),
+                    readiness: None,
+                }
+                .to_element(),
+            );
+        }
+        if let Some(takv) = &self.takv {
+            detail.append_child(
+                detail::Takv {
+                    device: non_empty(&takv.device),
+                    platform: non_empty(&takv.platform),
+                    os: non_empty(&takv.os),
+                    version: non_empty(&takv.version),
+                }
+                .to_element(),
+            );
+        }
+        if let Some(track) = &self.track {
+            detail.append_child(
+                detail::Track {
+                    speed: track.speed,
+                    course: track.course,
+                }
+                .to_element(),
+            );
+        }
+
+        if !self.xml_detail.is_empty() {
+            let wrapped = format!("<detail>{}</detail>", self.xml_detail);
+            xml_limits.check(wrapped.as_bytes())?;
+            let xml_detail =
+                Element::from_reader_with_prefixes(wrapped.as_bytes(), Some("".to_string()))
+                    .map_err(CodecError::XmlParse)?;
+            for child in xml_detail.children() {
+                detail.append_child(child.clone());
+            }
+        }
+        Ok(detail)
+    }
+}
+
+impl From<&Event> for CotEvent {
+    fn from(event: &Event) -> Self {
+        CotEvent {
+            r#type: event.event_type.clone(),
+            access: event.access.clone().unwrap_or_default(),
+            qos: event.qos.clone().unwrap_or_default(),
+            opex: event.other_attrs.get("opex").cloned().unwrap_or_default(),
+            uid: event.uid.clone(),
+            send_time: to_millis(event.time),
+            start_time: to_millis(event.start),
+            stale_time: to_millis(event.stale),
+            how: event.how.clone(),
+            lat: event.point.lat,
+            lon: event.point.lon,
+            hae: event.point.hae,
+            ce: event.point.ce,
+            le: event.point.le,
+            detail: event.detail.as_ref().map(|detail| detail.element().into()),
+        }
+    }
+}
+
+impl Event {
+    pub fn from_cot_event(
+        cot_event: &CotEvent,
+        xml_limits: &XmlLimits,
+    ) -> Result<Self, CodecError> {
+        Ok(Event {
+            version: "2.0".to_string(),
+            uid: cot_event.uid.clone(),
+            event_type: cot_event.r#type.clone(),
+            how: cot_event.how.clone(),
+            time: from_millis(cot_event.send_time, "time")?,
+            start: from_millis(cot_event.start_time, "start")?,
+            stale: from_millis(cot_event.stale_time, "stale")?,
+            access: non_empty(&cot_event.access),
+            qos: non_empty(&cot_event.qos),
+            point: Point {
+                lat: cot_event.lat,
+                lon: cot_event.lon,
+                hae: cot_event.hae,
+                ce: cot_event.ce,
+                le: cot_event.le,
+                other_attrs: Default::default(),
+                source: Default::default(),
+            },
+            detail: cot_event
+                .detail
+                .as_ref()
+                .map(|container| container.to_element(xml_limits).map(Detail::from_element))
+                .transpose()?,
+            other_attrs: non_empty(&cot_event.opex)
+                .map(|opex| ("opex".to_string(), opex))
+                .into_iter()
+                .collect(),
+            children: Default::default(),
+            source: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::xml::LimitViolation;
+    use minidom::NSChoice;
+
+    const FIXTURES: [&str; 6] = [
+        include_str!("../xml/fixtures/first_event.xml"),
+        include_str!("../xml/fixtures/additional.xml"),
+        include_str!("../xml/fixtures/911_alert_start.xml"),
+        include_str!("../xml/fixtures/911_deactive.xml"),
+        include_str!("../xml/fixtures/contact_alert.xml"),
+        include_str!("../xml/fixtures/general_chat_message.xml"),
+    ];
+
+    fn sorted_children(event: &Event) -> Vec<Element> {
+        let mut children: Vec<_> = event
+            .detail
+            .as_ref()
+            .map(|detail| detail.element().children().cloned().collect())
+            .unwrap_or_default();
+        children.sort_by(|a, b| a.name().cmp(b.name()));
+        children
+    }
+
+    #[test]
+    fn test_fixtures_survive_proto_round_trip() -> anyhow::Result<()> {
+        for fixture in FIXTURES {
+            let original = Message::from_raw_xml(fixture)?.to_event()?;
+            let tak_message = Message::from(original.clone()).to_tak_message()?;
+            let converted = Message::from_tak_message(tak_message, &Default::default())?
+                .expect("event present")
+                .to_event()?;
+
+            assert_eq!(converted.uid, original.uid);
+            assert_eq!(converted.event_type, original.event_type);
+            assert_eq!(converted.how, original.how);
+            assert_eq!(converted.time, original.time);
+            assert_eq!(converted.stale, original.stale);
+            assert_eq!(converted.point, original.point);
+            // typed proto fields are placed in front of xmlDetail ones
+            assert_eq!(sorted_children(&converted), sorted_children(&original));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_detail_fields_are_used() -> anyhow::Result<()> {
+        let message = Message::from_raw_xml(include_str!("../xml/fixtures/first_event.xml"))?;
+        let cot_event = message.to_tak_message()?.cot_event.expect("event");
+        let container = cot_event.detail.expect("detail");
+
+        // contact has phone attribute, proto Contact can not carry it
+        assert_eq!(container.contact, None);
+        assert_eq!(
+            container.group,
+            Some(messages::Group {
+                name: "Cyan".to_string(),
+                role: "Team Member".to_string()
+            })
+        );
+        assert_eq!(container.status, Some(messages::Status { battery: 70 }));
+        assert_eq!(
+            container.takv.map(|takv| takv.platform),
+            Some("iTAK".to_string())
+        );
+        assert_eq!(
+            container.track.map(|track| track.course),
+            Some(347.9174499511719)
+        );
+        assert_eq!(
+            container.xml_detail,
+            r#"<contact callsign="Aaaaa" endpoint="*:-1:stcp" phone=""/><uid Droid="Aaaaa"/>"#
+        );
+        assert_eq!(cot_event.send_time, 1703359436000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_codec() -> anyhow::Result<()> {
+        let mut codec = TakProtoCodec::new(64 * 1024);
+        let mut encoded = BytesMut::new();
+        for fixture in FIXTURES {
+            codec.encode(Message::from_raw_xml(fixture)?, &mut encoded)?;
+        }
+        assert_eq!(encoded[0], TAK_PROTO_MAGIC);
+
+        // feed bytes one by one, like the slowest possible network
+        let mut buffer = BytesMut::new();
+        let mut decoded = vec![];
+        for byte in encoded {
+            buffer.put_u8(byte);
+            if let Some(message) = codec.decode(&mut buffer)? {
+                decoded.push(message);
+            }
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(decoded.len(), FIXTURES.len());
+        for (message, fixture) in decoded.iter().zip(FIXTURES) {
+            assert_eq!(message.uid(), Message::from_raw_xml(fixture)?.uid());
+            assert!(message
+                .detail()
+                .is_some_and(|detail| detail.has_child("contact", NSChoice::Any)
+                    || detail.has_child("__chat", NSChoice::Any)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_control_only_message_is_skipped() -> anyhow::Result<()> {
+        let mut codec = TakProtoCodec::new(1024);
+        let control = TakMessage {
+            tak_control: Some(TakControl {
+                min_proto_version: 1,
+                max_proto_version: 1,
+                contact_uid: "ANDROID-1".to_string(),
+            }),
+            cot_event: None,
+        };
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(TAK_PROTO_MAGIC);
+        prost::encoding::encode_varint(control.encoded_len() as u64, &mut buffer);
+        control.encode(&mut buffer)?;
+        codec.encode(
+            Message::from_raw_xml(include_str!("../xml/fixtures/additional.xml"))?,
+            &mut buffer,
+        )?;
+
+        let message = codec.decode(&mut buffer)?.expect("event after control");
+        assert_eq!(message.event_type(), Some("a-f-G-E-V-C"));
+        assert!(codec.decode(&mut buffer)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_codec_errors() {
+        let mut codec = TakProtoCodec::new(16);
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&b"<event>"[..])),
+            Err(CodecError::InvalidMagicByte(b'<'))
+        ));
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&[TAK_PROTO_MAGIC, 0x80, 0x01][..])),
+            Err(CodecError::FrameTooLarge { size: 128, max: 16 })
+        ));
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(&[TAK_PROTO_MAGIC, 0x80][..])),
+            Ok(None)
+        ));
+        assert!(matches!(
+            codec.decode(&mut BytesMut::from(
+                &[TAK_PROTO_MAGIC, 0x02, 0xFF, 0xFF][..]
+            )),
+            Err(CodecError::ProtoDecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_xml_detail_over_limits() -> anyhow::Result<()> {
+        let sa = Message::from_raw_xml(include_str!("../xml/fixtures/additional.xml"))?;
+        let mut tak_message = sa.to_tak_message()?;
+        let container = tak_message
+            .cot_event
+            .as_mut()
+            .and_then(|cot_event| cot_event.detail.as_mut())
+            .expect("detail");
+        container.xml_detail = format!("{}{}", "<a>".repeat(32), "</a>".repeat(32));
+
+        let mut buffer = BytesMut::new();
+        put_header(tak_message.encoded_len(), &mut buffer);
+        tak_message.encode(&mut buffer)?;
+        let mut codec = TakProtoCodec::new(64 * 1024).with_xml_limits(Default::default());
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(CodecError::XmlLimit(LimitViolation::Depth(16)))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! routed message shared by every receiver, each wire form is encoded once for all of them
+
+use super::{CodecError, Message, ValidationError};
+use prost::Message as _;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+use tokio_util::bytes::Bytes;
+
+struct Inner {
+    message: Message,
+    xml: OnceLock<Bytes>,
+    /// protobuf `TakMessage` without any framing, stream and mesh headers differ
+    proto: OnceLock<Result<Bytes, ValidationError>>,
+}
+
+/// parsed message with lazily encoded frames, clones are cheap and share encodings;
+/// whichever writer needs a form first encodes it, the rest just copy bytes
+#[derive(Clone)]
+pub struct SharedMessage(Arc<Inner>);
+
+impl SharedMessage {
+    pub fn new(message: Message) -> Self {
+        Self(Arc::new(Inner {
+            message,
+            xml: OnceLock::new(),
+            proto: OnceLock::new(),
+        }))
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.0.message
+    }
+
+    /// xml form, as written by legacy streaming and mesh
+    pub fn xml(&self) -> Result<&Bytes, CodecError> {
+        if let Some(xml) = self.0.xml.get() {
+            return Ok(xml);
+        }
+        let mut buff = Vec::new();
+        self.0.message.as_xml(&mut buff)?;
+        // racing writer may have won, its bytes are the same
+        Ok(self.0.xml.get_or_init(|| buff.into()))
+    }
+
+    /// encoded `TakMessage`, fails for messages which are not valid events
+    pub fn proto_payload(&self) -> Result<&Bytes, CodecError> {
+        if self.0.proto.get().is_none() {
+            let payload = match self.0.message.to_tak_message() {
+                Ok(tak_message) => Ok(tak_message.encode_to_vec().into()),
+                Err(CodecError::Validation(e)) => Err(e),
+                Err(e) => return Err(e),
+            };
+            let _ = self.0.proto.set(payload);
+        }
+        match self.0.proto.get().expect("proto payload is set") {
+            Ok(payload) => Ok(payload),
+            Err(e) => Err(e.clone().into()),
+        }
+    }
+}
+
+impl From<Message> for SharedMessage {
+    fn from(message: Message) -> Self {
+        Self::new(message)
+    }
+}
+
+impl Deref for SharedMessage {
+    type Target = Message;
+
+    fn deref(&self) -> &Message {
+        self.message()
+    }
+}
+
+impl std::fmt::Debug for SharedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.message.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encodings_are_shared_by_clones() -> anyhow::Result<()> {
+        let sa = Message::from_raw_xml(include_str!("xml/fixtures/first_event.xml"))?;
+        let shared = SharedMessage::new(sa.clone());
+        let clone = shared.clone();
+
+        let mut xml = Vec::new();
+        sa.as_xml(&mut xml)?;
+        assert_eq!(shared.xml()?.as_ref(), xml.as_slice());
+        assert_eq!(
+            shared.proto_payload()?.as_ref(),
+            sa.to_tak_message()?.encode_to_vec().as_slice()
+        );
+        // same allocation, not a copy
+        assert_eq!(clone.xml()?.as_ptr(), shared.xml()?.as_ptr());
+        assert_eq!(
+            clone.proto_payload()?.as_ptr(),
+            shared.proto_payload()?.as_ptr()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_event_fails_every_time() -> anyhow::Result<()> {
+        let shared = SharedMessage::new(Message::from_raw_xml(r#"<event uid="a"/>"#)?);
+        assert!(shared.xml().is_ok());
+        for _ in 0..2 {
+            assert!(matches!(
+                shared.proto_payload(),
+                Err(CodecError::Validation(_))
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+//! finds where an event ends in a stream of xml without parsing it; markup aware, so `</event>`
+//! inside CDATA, comments or attribute values does not cut the frame
+//!
+//! frame is the root element only, xml declaration, comments and whitespace around it are
+//! skipped because parser accepts none of them; comments inside the root do not break framing,
+//! but parser rejects them as well; elements nested in the root and named like it are not
+//! supported, events never contain other events
+//!
+//! scanning is incremental, bytes already looked at are not scanned again when more arrive
+
+use memchr::{memchr, memchr3, memmem};
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Scan {
+    /// root element spans `start..end`
+    Frame { start: usize, end: usize },
+    /// more bytes needed
+    Incomplete,
+    /// text outside of any element starts at given position
+    StrayText(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// between markup
+    Text,
+    /// `<` at given position, not yet known what it opens
+    MarkupStart(usize),
+    /// comment, CDATA or processing instruction, waiting for its terminator
+    Until(&'static [u8]),
+    /// start or end tag opened at given position
+    Tag {
+        start: usize,
+        end_tag: bool,
+        quote: Option<u8>,
+    },
+    /// `<!DOCTYPE` and alike, internal subset in brackets included
+    Declaration { quote: Option<u8>, brackets: usize },
+}
+
+/// remembers progress in a buffer which only grows at the end,
+/// [`FrameScanner::reset`] has to be called whenever bytes are removed from its front
+#[derive(Debug)]
+pub(crate) struct FrameScanner {
+    /// next byte to look at
+    pos: usize,
+    state: State,
+    /// start and name of the root element once its start tag is complete
+    root: Option<(usize, Range<usize>)>,
+}
+
+impl Default for FrameScanner {
+    fn default() -> Self {
+        Self {
+            pos: 0,
+            state: State::Text,
+            root: None,
+        }
+    }
+}
+
+/// true if `rest` may still turn into `pattern` once more bytes arrive
+fn is_partial(rest: &[u8], pattern: &[u8]) -> bool {
+    rest.len() < pattern.len() && pattern.starts_with(rest)
+}
+
+fn tag_name_len(tag: &[u8]) -> usize {
+    tag.iter()
+        .position(|b| b.is_ascii_whitespace() || matches!(b, b'/' | b'>'))
+        .unwrap_or(tag.len())
+}
+
+impl FrameScanner {
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// looks for the first complete root element in `buf`, continuing where previous call stopped
+    pub(crate) fn scan(&mut self, buf: &[u8]) -> Scan {
+        loop {
+            match self.state {
+                State::Text => {
+                    let text_end = memchr(b'<', &buf[self.pos..]).map(|lt| self.pos + lt);
+                    if self.root.is_none() {
+                        let text = &buf[self.pos..text_end.unwrap_or(buf.len())];
+                        if let Some(stray) = text.iter().position(|b| !b.is_ascii_whitespace()) {
+                            return Scan::StrayText(self.pos + stray);
+                        }
+                    }
+                    let Some(lt) = text_end else {
+                        self.pos = buf.len();
+                        return Scan::Incomplete;
+                    };
+                    self.state = State::MarkupStart(lt);
+                    self.pos = lt;
+                }
+                State::MarkupStart(lt) => {
+                    let rest = &buf[lt..];
+                    if rest.len() < 2 || is_partial(rest, b"<!--") || is_partial(rest, b"<![CDATA[")
+                    {
+                        return Scan::Incomplete;
+                    }
+                    (self.state, self.pos) = if rest.starts_with(b"<!--") {
+                        (State::Until(b"-->"), lt + 4)
+                    } else if rest.starts_with(b"<![CDATA[") {
+                        (State::Until(b"]]>"), lt + 9)
+                    } else if rest.starts_with(b"<?") {
+                        (State::Until(b"?>"), lt + 2)
+                    } else if rest.starts_with(b"<!") {
+                        let declaration = State::Declaration {
+                            quote: None,
+                            brackets: 0,
+                        };
+                        (declaration, lt + 2)
+                    } else {
+                        let end_tag = rest[1] == b'/';
+                        let tag = State::Tag {
+                            start: lt,
+                            end_tag,
+                            quote: None,
+                        };
+                        (tag, lt + if end_tag { 2 } else { 1 })
+                    };
+                }
+                State::Until(terminator) => match memmem::find(&buf[self.pos..], terminator) {
+                    Some(found) => {
+                        self.pos += found + terminator.len();
+                        self.state = State::Text;
+                    }
+                    None => {
+                        // terminator may be split between this and the next read
+                        let keep = (terminator.len() - 1).min(buf.len() - self.pos);
+                        self.pos = buf.len() - keep;
+                        return Scan::Incomplete;
+                    }
+                },
+                State::Tag {
+                    start,
+                    end_tag,
+                    quote,
+                } => {
+                    let rest = &buf[self.pos..];
+                    let found = match quote {
+                        Some(q) => memchr(q, rest),
+                        None => memchr3(b'>', b'"', b'\'', rest),
+                    };
+                    let Some(found) = found else {
+                        self.pos = buf.len();
+                        return Scan::Incomplete;
+                    };
+                    let at = self.pos + found;
+                    self.pos = at + 1;
+                    let quote = match (quote, buf[at]) {
+                        (Some(_), _) => None,
+                        (None, b'"' | b'\'') => Some(buf[at]),
+                        (None, _) => {
+                            self.state = State::Text;
+                            if let Some(frame) = self.tag_closed(buf, start, end_tag) {
+                                return frame;
+                            }
+                            continue;
+                        }
+                    };
+                    self.state = State::Tag {
+                        start,
+                        end_tag,
+                        quote,
+                    };
+                }
+                State::Declaration { quote, brackets } => {
+                    let Some(&b) = buf.get(self.pos) else {
+                        return Scan::Incomplete;
+                    };
+                    self.pos += 1;
+                    self.state = match (quote, b) {
+                        (Some(q), _) if q == b => State::Declaration {
+                            quote: None,
+                            brackets,
+                        },
+                        (Some(_), _) => continue,
+                        (None, b'"' | b'\'') => State::Declaration {
+                            quote: Some(b),
+                            brackets,
+                        },
+                        (None, b'[') => State::Declaration {
+                            quote,
+                            brackets: brackets + 1,
+                        },
+                        (None, b']') => State::Declaration {
+                            quote,
+                            brackets: brackets.saturating_sub(1),
+                        },
+                        (None, b'>') if brackets == 0 => State::Text,
+                        (None, _) => continue,
+                    };
+                }
+            }
+        }
+    }
+
+    /// tag from `start` up to `self.pos` is complete, frame if it closes the root
+    fn tag_closed(&mut self, buf: &[u8], start: usize, end_tag: bool) -> Option<Scan> {
+        let end = self.pos;
+        match &self.root {
+            None if end_tag => None,
+            None if buf[end - 2] == b'/' => Some(Scan::Frame { start, end }),
+            None => {
+                let name_start = start + 1;
+                let name_len = tag_name_len(&buf[name_start..end]);
+                self.root = Some((start, name_start..name_start + name_len));
+                None
+            }
+            Some((root_start, root_name)) if end_tag => {
+                let name_start = start + 2;
+                let name_len = tag_name_len(&buf[name_start..end]);
+                let closes_root = buf[name_start..name_start + name_len] == buf[root_name.clone()];
+                closes_root.then_some(Scan::Frame {
+                    start: *root_start,
+                    end,
+                })
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find_frame(buf: &[u8]) -> Scan {
+        FrameScanner::default().scan(buf)
+    }
+
+    #[test]
+    fn test_markup_is_skipped() {
+        let frame = br#"<event uid="a"><!-- </event> --><remarks a="</event>"><![CDATA[</event>]]></remarks></event>"#;
+        assert_eq!(
+            find_frame(frame),
+            Scan::Frame {
+                start: 0,
+                end: frame.len()
+            }
+        );
+    }
+
+    #[test]
+    fn test_prologue_and_whitespace_are_not_part_of_frame() {
+        let stream =
+            b"\r\n<?xml version=\"1.0\" standalone=\"yes\"?>\n<!-- hi --><event uid=\"a\"/>\n";
+        let start = stream.len() - 17;
+        assert_eq!(
+            find_frame(stream),
+            Scan::Frame {
+                start,
+                end: start + 16
+            }
+        );
+        assert_eq!(find_frame(b"\n<?xml version=\"1.0\"?>\n"), Scan::Incomplete);
+    }
+
+    #[test]
+    fn test_incomplete_markup() {
+        for partial in [
+            "<",
+            "<!-",
+            "<![CDA",
+            "<event uid=\"a>\"",
+            "<event><!-- </event>",
+            "<event><![CDATA[ </event> ]]",
+            "<event></even",
+        ] {
+            assert_eq!(
+                find_frame(partial.as_bytes()),
+                Scan::Incomplete,
+                "{partial}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_stray_text() {
+        assert_eq!(find_frame(b" \njunk<event/>"), Scan::StrayText(2));
+    }
+
+    #[test]
+    fn test_scan_resumes() {
+        let stream = br#"<!DOCTYPE event [<!ENTITY x ">">]><event uid='a>'><remarks><![CDATA[]]]]><!-- -- --></remarks></event>"#;
+        let mut scanner = FrameScanner::default();
+        for len in 0..stream.len() {
+            assert_eq!(scanner.scan(&stream[..len]), Scan::Incomplete, "{len}");
+            // only a possibly split terminator or markup opening is looked at again
+            assert!(scanner.pos + 9 >= len, "{len}");
+        }
+        assert_eq!(
+            scanner.scan(stream),
+            Scan::Frame {
+                start: 34,
+                end: stream.len()
+            }
+        );
+    }
+}
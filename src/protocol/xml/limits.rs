@@ -0,0 +1,193 @@
+//! cheap pass over an untrusted frame before it reaches the parser, bounds how much work
+//! and memory a single event may cost; it only counts, anything malformed is left to the parser
+
+use memchr::{memchr, memchr3, memchr_iter, memmem};
+
+/// per event limits, the whole frame is checked including anything before its root element
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct XmlLimits {
+    /// elements nested in each other, event itself is at depth 1
+    pub max_depth: usize,
+    pub max_elements: usize,
+    /// attributes of all elements together
+    pub max_attributes: usize,
+    /// bytes of character data and CDATA as they are on the wire, entities are not expanded
+    pub max_text_len: usize,
+}
+
+impl Default for XmlLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 16,
+            max_elements: 256,
+            max_attributes: 1024,
+            max_text_len: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LimitViolation {
+    #[error("elements nested deeper than {0}")]
+    Depth(usize),
+    #[error("more than {0} elements")]
+    Elements(usize),
+    #[error("more than {0} attributes")]
+    Attributes(usize),
+    #[error("more than {0} bytes of text")]
+    TextLength(usize),
+    /// `<!DOCTYPE`, `<!ENTITY` and alike, events never need them
+    #[error("document type or entity declaration")]
+    Declaration,
+}
+
+/// position right after `terminator` found at or after `from`, end of `xml` if there is none
+fn skip_past(xml: &[u8], from: usize, terminator: &[u8]) -> usize {
+    memmem::find(&xml[from..], terminator)
+        .map_or(xml.len(), |found| from + found + terminator.len())
+}
+
+/// length up to and including `>`, attribute count and whether it is self-closing
+fn start_tag(tag: &[u8]) -> (usize, usize, bool) {
+    let mut attributes = 0;
+    let mut pos = 0;
+    while let Some(found) = memchr3(b'>', b'"', b'\'', &tag[pos..]) {
+        let at = pos + found;
+        attributes += memchr_iter(b'=', &tag[pos..at]).count();
+        if tag[at] == b'>' {
+            return (at + 1, attributes, tag[at - 1] == b'/');
+        }
+        let Some(closing) = memchr(tag[at], &tag[at + 1..]) else {
+            break;
+        };
+        pos = at + 1 + closing + 1;
+    }
+    (tag.len(), attributes, false)
+}
+
+impl XmlLimits {
+    pub fn check(&self, xml: &[u8]) -> Result<(), LimitViolation> {
+        let mut depth = 0usize;
+        let mut elements = 0;
+        let mut attributes = 0;
+        let mut text = 0;
+        let mut pos = 0;
+        while pos < xml.len() {
+            let markup = memchr(b'<', &xml[pos..]).map_or(xml.len(), |lt| pos + lt);
+            text += markup - pos;
+            if text > self.max_text_len {
+                return Err(LimitViolation::TextLength(self.max_text_len));
+            }
+            let rest = &xml[markup..];
+            pos = if rest.is_empty() {
+                markup
+            } else if rest.starts_with(b"<!--") {
+                skip_past(xml, markup + 4, b"-->")
+            } else if rest.starts_with(b"<![CDATA[") {
+                let end = skip_past(xml, markup + 9, b"]]>");
+                text += end.saturating_sub(markup + 9 + 3);
+                if text > self.max_text_len {
+                    return Err(LimitViolation::TextLength(self.max_text_len));
+                }
+                end
+            } else if rest.starts_with(b"<?") {
+                skip_past(xml, markup + 2, b"?>")
+            } else if rest.starts_with(b"<!") {
+                return Err(LimitViolation::Declaration);
+            } else if rest.starts_with(b"</") {
+                depth = depth.saturating_sub(1);
+                skip_past(xml, markup + 2, b">")
+            } else {
+                let (len, tag_attributes, self_closing) = start_tag(rest);
+                elements += 1;
+                if elements > self.max_elements {
+                    return Err(LimitViolation::Elements(self.max_elements));
+                }
+                attributes += tag_attributes;
+                if attributes > self.max_attributes {
+                    return Err(LimitViolation::Attributes(self.max_attributes));
+                }
+                if !self_closing {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(LimitViolation::Depth(self.max_depth));
+                    }
+                }
+                markup + len
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LIMITS: XmlLimits = XmlLimits {
+        max_depth: 3,
+        max_elements: 4,
+        max_attributes: 4,
+        max_text_len: 8,
+    };
+
+    #[test]
+    fn test_within_limits() {
+        let frames: [&[u8]; 3] = [
+            br#"<?xml version="1.0"?><!-- <a><b><c><d> --><event uid="a" how="x"><detail><a b="=>"/></detail></event>"#,
+            br#"<event><detail><remarks><![CDATA[<a><b>]]></remarks></detail></event>"#,
+            br#"<event a='"' b="'"><x/><x/><x/></event>"#,
+        ];
+        for frame in frames {
+            assert_eq!(
+                LIMITS.check(frame),
+                Ok(()),
+                "{}",
+                String::from_utf8_lossy(frame)
+            );
+        }
+    }
+
+    #[test]
+    fn test_violations() {
+        let frames: [(&[u8], _); 7] = [
+            (
+                b"<event><a><b><c></c></b></a></event>",
+                LimitViolation::Depth(3),
+            ),
+            (
+                b"<event><x/><x/><x/><x/></event>",
+                LimitViolation::Elements(4),
+            ),
+            (
+                br#"<event a="1" b="2"><x c="3" d="4" e="5"/></event>"#,
+                LimitViolation::Attributes(4),
+            ),
+            (
+                b"<event>1234<x/>56789</event>",
+                LimitViolation::TextLength(8),
+            ),
+            (
+                b"<event><![CDATA[123456789]]></event>",
+                LimitViolation::TextLength(8),
+            ),
+            (
+                br#"<!DOCTYPE event [<!ENTITY x "y">]><event/>"#,
+                LimitViolation::Declaration,
+            ),
+            (
+                br#"<event><!ENTITY x "y"></event>"#,
+                LimitViolation::Declaration,
+            ),
+        ];
+        for (frame, violation) in frames {
+            assert_eq!(
+                LIMITS.check(frame),
+                Err(violation),
+                "{}",
+                String::from_utf8_lossy(frame)
+            );
+        }
+    }
+}
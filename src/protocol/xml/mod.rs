@@ -1,20 +1,68 @@
-use crate::protocol::Message;
+mod framing;
+mod limits;
+
+use crate::protocol::{Message, SharedMessage};
+use framing::{FrameScanner, Scan};
+use memchr::memmem;
 use minidom::Element;
-use tokio_util::bytes::BytesMut;
+use tokio_util::bytes::{Buf, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
+use tracing::warn;
+
+pub use limits::{LimitViolation, XmlLimits};
 
-pub const COT_LEGACY_FRAME_MARKER: &[u8] = b"</event>";
+const EVENT_START: &[u8] = b"<event";
 
 pub struct CotLegacyCodec {
     buff: Vec<u8>,
+    scanner: FrameScanner,
+    max_frame_size: usize,
+    limits: XmlLimits,
+    /// malformed and oversized frames are skipped instead of failing
+    resync: bool,
 }
 
 impl CotLegacyCodec {
-    pub fn new(buf_size: usize) -> Self {
+    /// frames over `max_frame_size` bytes are errors
+    pub fn new(max_frame_size: usize) -> Self {
         Self {
-            buff: Vec::with_capacity(buf_size),
+            buff: Vec::new(),
+            scanner: Default::default(),
+            max_frame_size,
+            limits: Default::default(),
+            resync: false,
         }
     }
+
+    /// events over `limits` are treated like malformed ones
+    pub fn with_limits(self, limits: XmlLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// skips malformed or oversized frames and continues with the next `<event`
+    pub fn lenient(max_frame_size: usize) -> Self {
+        Self {
+            resync: true,
+            ..Self::new(max_frame_size)
+        }
+    }
+
+    /// drops bytes from the front of `src`, scan progress refers to them so it starts over
+    fn consume(&mut self, src: &mut BytesMut, len: usize) {
+        src.advance(len);
+        self.scanner.reset();
+    }
+}
+
+/// position of the next `<event` at or after `from`, or of the tail which may be the beginning of one
+fn next_event_start(src: &[u8], from: usize) -> usize {
+    let from = from.min(src.len());
+    match memmem::find(&src[from..], EVENT_START) {
+        Some(pos) => from + pos,
+        None => (from..src.len())
+            .find(|&pos| EVENT_START.starts_with(&src[pos..]))
+            .unwrap_or(src.len()),
+    }
 }
 
 impl Decoder for CotLegacyCodec {
@@ -22,13 +70,78 @@ impl Decoder for CotLegacyCodec {
     type Error = super::CodecError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        Ok(if let Some(pos) = find_in(src, COT_LEGACY_FRAME_MARKER) {
-            let frame = src.split_to(pos + COT_LEGACY_FRAME_MARKER.len());
-            let element = xml_parse(frame.as_ref()).map_err(super::CodecError::XmlParse)?;
-            Some(Message::Xml(element))
-        } else {
-            None
-        })
+        loop {
+            let (start, end) = match self.scanner.scan(src) {
+                Scan::Frame { start, end } => (start, end),
+                Scan::Incomplete if src.len() <= self.max_frame_size => return Ok(None),
+                Scan::Incomplete => {
+                    if !self.resync {
+                        return Err(super::CodecError::FrameTooLarge {
+                            size: src.len(),
+                            max: self.max_frame_size,
+                        });
+                    }
+                    let skipped = next_event_start(src, 1);
+                    self.consume(src, skipped);
+                    warn!("Skipped {skipped} bytes of oversized xml frame");
+                    continue;
+                }
+                Scan::StrayText(at) => {
+                    if !self.resync {
+                        self.consume(src, at + 1);
+                        return Err(super::CodecError::XmlFraming(
+                            "text outside of event".to_string(),
+                        ));
+                    }
+                    let skipped = next_event_start(src, at);
+                    self.consume(src, skipped);
+                    warn!("Skipped {skipped} bytes before xml frame");
+                    continue;
+                }
+            };
+
+            if end > self.max_frame_size {
+                if !self.resync {
+                    return Err(super::CodecError::FrameTooLarge {
+                        size: end,
+                        max: self.max_frame_size,
+                    });
+                }
+                self.consume(src, end);
+                warn!("Skipped oversized xml frame of {end} bytes");
+                continue;
+            }
+
+            // prologue may hide a document type declaration, so it is checked as well
+            if let Err(violation) = self.limits.check(&src[..end]) {
+                self.consume(src, end);
+                if !self.resync {
+                    return Err(violation.into());
+                }
+                warn!("Skipped xml frame of {end} bytes: {violation}");
+                continue;
+            }
+
+            match xml_parse(&src[start..end]) {
+                Ok(element) => {
+                    self.consume(src, end);
+                    return Ok(Some(Message::Xml(element)));
+                }
+                Err(e) if self.resync => {
+                    // frame may be the tail of a broken one followed by a good event
+                    let skipped = match memmem::find(&src[start + 1..end], EVENT_START) {
+                        Some(next) => start + 1 + next,
+                        None => end,
+                    };
+                    self.consume(src, skipped);
+                    warn!("Skipped {skipped} bytes of malformed xml frame: {e}");
+                }
+                Err(e) => {
+                    self.consume(src, end);
+                    return Err(super::CodecError::XmlParse(e));
+                }
+            }
+        }
     }
 }
 
@@ -43,15 +156,16 @@ impl Encoder<Message> for CotLegacyCodec {
     }
 }
 
-fn find_in(slice: &[u8], subslice: &[u8]) -> Option<usize> {
-    slice
-        .windows(subslice.len())
-        .enumerate()
-        .find(|&(_, window)| window == subslice)
-        .map(|(pos, _)| pos)
+impl Encoder<SharedMessage> for CotLegacyCodec {
+    type Error = super::CodecError;
+
+    fn encode(&mut self, item: SharedMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.extend_from_slice(item.xml()?);
+        Ok(())
+    }
 }
 
-fn xml_parse(xml: &[u8]) -> minidom::Result<Element> {
+pub(crate) fn xml_parse(xml: &[u8]) -> minidom::Result<Element> {
     // event xml element comes without ns, treat it as empty
     Element::from_reader_with_prefixes(xml, Some("".to_string()))
 }
@@ -61,6 +175,12 @@ mod test {
     use super::*;
     use tokio_util::bytes::{BufMut, BytesMut};
 
+    macro_rules! xml_test_message {
+        ($name: literal) => {
+            ($name, include_bytes!($name).as_slice())
+        };
+    }
+
     #[tokio::test]
     async fn xml_decoder_test() -> anyhow::Result<()> {
         let data = b"<event>something something</event><event>something again";
@@ -97,11 +217,186 @@ mod test {
         Ok(())
     }
 
-    macro_rules! xml_test_message {
-        ($name: literal) => {
-            ($name, include_bytes!($name).as_slice())
+    fn decode_uids(decoder: &mut CotLegacyCodec, buffer: &mut BytesMut) -> Vec<String> {
+        let mut uids = Vec::new();
+        while let Some(message) = decoder.decode(buffer).expect("lenient decoder") {
+            uids.push(message.uid().unwrap_or_default().to_string());
+        }
+        uids
+    }
+
+    #[test]
+    fn xml_decoder_frame_size_limit() {
+        let mut decoder = CotLegacyCodec::new(32);
+        let mut buffer = BytesMut::from(r#"<event uid="a"><detail>"#.as_bytes());
+        assert!(decoder.decode(&mut buffer).expect("under limit").is_none());
+
+        buffer.put_slice(&[b'x'; 16]);
+        assert!(matches!(
+            decoder.decode(&mut buffer),
+            Err(crate::protocol::CodecError::FrameTooLarge { size: 39, max: 32 })
+        ));
+
+        let mut buffer = BytesMut::from(r#"<event uid="a">too long for limit</event>"#.as_bytes());
+        assert!(matches!(
+            decoder.decode(&mut buffer),
+            Err(crate::protocol::CodecError::FrameTooLarge { size: 41, max: 32 })
+        ));
+    }
+
+    #[test]
+    fn xml_decoder_strict_fails_on_malformed_frame() {
+        let mut decoder = CotLegacyCodec::new(1024);
+        let mut buffer =
+            BytesMut::from(r#"<event uid="a"><oops></event><event uid="b"></event>"#.as_bytes());
+        assert!(matches!(
+            decoder.decode(&mut buffer),
+            Err(crate::protocol::CodecError::XmlParse(_))
+        ));
+    }
+
+    #[test]
+    fn xml_decoder_lenient_resyncs() {
+        let mut decoder = CotLegacyCodec::lenient(1024);
+        let mut buffer = BytesMut::from(
+            concat!(
+                "garbage",
+                r#"<event uid="a"></event>"#,
+                "\n",
+                r#"<event uid="broken"><oops></event>"#,
+                r#"<event uid="cut"><detail><event uid="b"></event>"#,
+                r#"<event uid="c"></event>"#,
+            )
+            .as_bytes(),
+        );
+        assert_eq!(decode_uids(&mut decoder, &mut buffer), ["a", "b", "c"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn xml_decoder_lenient_skips_oversized() {
+        let mut decoder = CotLegacyCodec::lenient(32);
+        let mut buffer = BytesMut::from(r#"<event uid="a">too long for limit</event>"#.as_bytes());
+        buffer.put_slice(br#"<event uid="b"></event>"#);
+        assert_eq!(decode_uids(&mut decoder, &mut buffer), ["b"]);
+
+        // endless frame does not grow the buffer beyond limit
+        buffer.put_slice(br#"<event uid="endless">"#);
+        for _ in 0..100 {
+            buffer.put_slice(&[b'x'; 16]);
+            assert_eq!(decode_uids(&mut decoder, &mut buffer), Vec::<String>::new());
+            assert!(buffer.len() <= 32);
+        }
+        buffer.put_slice(br#"<event uid="c"></event><ev"#);
+        assert_eq!(decode_uids(&mut decoder, &mut buffer), ["c"]);
+        assert_eq!(buffer.as_ref(), b"<ev");
+    }
+
+    #[test]
+    fn xml_decoder_framing_corpus() {
+        let streams = [
+            (
+                xml_test_message!("fixtures/framing/itak_prologue.xml"),
+                &["itak-1", "itak-2"][..],
+            ),
+            (
+                xml_test_message!("fixtures/framing/wintak_crlf.xml"),
+                &["wintak-1", "wintak-2", "wintak-3"],
+            ),
+            (
+                xml_test_message!("fixtures/framing/remarks_cdata.xml"),
+                &["cdata-1", "cdata-2"],
+            ),
+            (
+                xml_test_message!("fixtures/framing/remarks_escaped.xml"),
+                &["escaped-1", "escaped-2"],
+            ),
+            (
+                xml_test_message!("fixtures/framing/comments.xml"),
+                &["comment-1", "comment-2"],
+            ),
+            (
+                xml_test_message!("fixtures/framing/self_closing.xml"),
+                &["self-closing-1", "self-closing-2"],
+            ),
+        ];
+
+        for ((name, content), expected) in streams {
+            let mut decoder = CotLegacyCodec::new(4096);
+            let mut buffer = BytesMut::from(content);
+            let uids = decode_uids(&mut decoder, &mut buffer);
+            assert_eq!(uids, expected, "whole {name}");
+            assert!(
+                buffer.iter().all(u8::is_ascii_whitespace),
+                "{name} leftover"
+            );
+
+            // frames split at every possible point
+            let mut decoder = CotLegacyCodec::new(4096);
+            let mut buffer = BytesMut::new();
+            let mut uids = Vec::new();
+            for byte in content {
+                buffer.put_u8(*byte);
+                uids.extend(decode_uids(&mut decoder, &mut buffer));
+            }
+            assert_eq!(uids, expected, "byte by byte {name}");
+        }
+    }
+
+    #[test]
+    fn xml_decoder_keeps_marker_in_cdata() -> anyhow::Result<()> {
+        let mut decoder = CotLegacyCodec::new(4096);
+        let mut buffer =
+            BytesMut::from(include_bytes!("fixtures/framing/remarks_cdata.xml").as_slice());
+        let message = decoder.decode(&mut buffer)?.expect("frame");
+        let remarks = message
+            .detail()
+            .and_then(|detail| detail.get_child("remarks", minidom::NSChoice::Any))
+            .map(|remarks| remarks.text());
+        assert_eq!(
+            remarks.as_deref(),
+            Some(r#"quoted </event><event uid="fake"> inside"#)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn xml_decoder_strict_fails_on_stray_text() {
+        let mut decoder = CotLegacyCodec::new(1024);
+        let mut buffer = BytesMut::from(r#"junk<event uid="a"/>"#.as_bytes());
+        assert!(matches!(
+            decoder.decode(&mut buffer),
+            Err(crate::protocol::CodecError::XmlFraming(_))
+        ));
+    }
+
+    #[test]
+    fn xml_decoder_limits() {
+        let limits = XmlLimits {
+            max_depth: 2,
+            ..Default::default()
         };
+        let stream = concat!(
+            r#"<event uid="deep"><detail><contact/><a><b/></a></detail></event>"#,
+            r#"<!DOCTYPE event [<!ENTITY lol "lol">]><event uid="entity">&lol;</event>"#,
+            r#"<event uid="a"><detail><contact/></detail></event>"#,
+        );
+
+        let mut decoder = CotLegacyCodec::new(1024).with_limits(limits);
+        let mut buffer = BytesMut::from(stream.as_bytes());
+        for violation in [LimitViolation::Depth(2), LimitViolation::Declaration] {
+            assert!(matches!(
+                decoder.decode(&mut buffer),
+                Err(crate::protocol::CodecError::XmlLimit(v)) if v == violation
+            ));
+        }
+        assert_eq!(decode_uids(&mut decoder, &mut buffer), ["a"]);
+
+        let mut decoder = CotLegacyCodec::lenient(1024).with_limits(limits);
+        let mut buffer = BytesMut::from(stream.as_bytes());
+        assert_eq!(decode_uids(&mut decoder, &mut buffer), ["a"]);
     }
+
     #[test]
     fn xml_message_parser() {
         let messages = [
@@ -0,0 +1,76 @@
+use std::time::Instant;
+
+/// sustained rate and burst size of messages accepted from a source
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_second: 50.0,
+            burst: 100,
+        }
+    }
+}
+
+/// token bucket, starts full
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    /// takes a token if there is one
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(
+            RateLimit {
+                per_second: 2.0,
+                burst: 3,
+            },
+            start,
+        );
+        assert_eq!((0..5).filter(|_| bucket.try_acquire(start)).count(), 3);
+
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_acquire(later));
+        assert!(!bucket.try_acquire(later));
+
+        // refill never exceeds burst
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!((0..5).filter(|_| bucket.try_acquire(much_later)).count(), 3);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::protocol::detail::{DetailElement, Emergency, Link};
+use crate::protocol::SharedMessage;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+const DELETE_EVENT_TYPE: &str = "t-x-d-d";
+const CHAT_EVENT_TYPE: &str = "b-t-f";
+
+struct CachedEvent {
+    message: SharedMessage,
+    stale: OffsetDateTime,
+}
+
+/// latest event per uid, replayed to late joiners
+#[derive(Default)]
+pub struct SaCache {
+    events: HashMap<String, CachedEvent>,
+}
+
+impl SaCache {
+    /// remembers broadcasted event, deletes and cancelled alerts evict cached ones
+    pub fn update(&mut self, message: &SharedMessage) {
+        let (Some(uid), Some(event_type)) = (message.uid(), message.event_type()) else {
+            return;
+        };
+
+        if event_type == DELETE_EVENT_TYPE {
+            if let Some(link) = message.detail().and_then(Link::find_in) {
+                self.events.remove(&link.uid);
+            }
+            return;
+        }
+
+        let cancelled = message
+            .detail()
+            .and_then(Emergency::find_in)
+            .is_some_and(|emergency| emergency.cancel);
+        if cancelled {
+            self.events.remove(uid);
+            return;
+        }
+
+        // pings, protocol control and chats are not situational awareness
+        if event_type.starts_with("t-") || event_type == CHAT_EVENT_TYPE {
+            return;
+        }
+
+        if let Some(stale) = message.stale() {
+            self.events.insert(
+                uid.to_string(),
+                CachedEvent {
+                    message: message.clone(),
+                    stale,
+                },
+            );
+        }
+    }
+
+    pub fn remove(&mut self, uid: &str) {
+        self.events.remove(uid);
+    }
+
+    /// drops events which are stale at given time, returns how many were dropped
+    pub fn expire(&mut self, now: OffsetDateTime) -> usize {
+        let before = self.events.len();
+        self.events.retain(|_, event| event.stale > now);
+        before - self.events.len()
+    }
+
+    /// events which are not stale at given time
+    pub fn fresh(&self, now: OffsetDateTime) -> impl Iterator<Item = &SharedMessage> {
+        self.events
+            .values()
+            .filter(move |event| event.stale > now)
+            .map(|event| &event.message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::Message;
+    use time::macros::datetime;
+
+    fn event(uid: &str, event_type: &str, stale: &str, detail: &str) -> SharedMessage {
+        raw(&format!(
+            r#"<event uid="{uid}" type="{event_type}" stale="{stale}"><detail>{detail}</detail></event>"#
+        ))
+    }
+
+    fn raw(xml: &str) -> SharedMessage {
+        Message::from_raw_xml(xml).expect("valid xml").into()
+    }
+
+    fn fresh_uids(cache: &SaCache, now: OffsetDateTime) -> Vec<String> {
+        let mut uids: Vec<_> = cache
+            .fresh(now)
+            .filter_map(|message| message.uid().map(str::to_string))
+            .collect();
+        uids.sort();
+        uids
+    }
+
+    #[test]
+    fn test_latest_event_per_uid_is_kept() {
+        let mut cache = SaCache::default();
+        cache.update(&event("A", "a-f-G-U-C", "2023-12-23T19:30:00Z", ""));
+        cache.update(&event("A", "a-f-G-U-C", "2023-12-23T19:40:00Z", ""));
+        cache.update(&event("M", "a-u-G", "2023-12-23T19:35:00Z", ""));
+
+        assert_eq!(
+            fresh_uids(&cache, datetime!(2023-12-23 19:20 UTC)),
+            vec!["A", "M"]
+        );
+        assert_eq!(
+            fresh_uids(&cache, datetime!(2023-12-23 19:36 UTC)),
+            vec!["A"]
+        );
+    }
+
+    #[test]
+    fn test_stale_events_expire() {
+        let mut cache = SaCache::default();
+        cache.update(&event("A", "a-f-G-U-C", "2023-12-23T19:30:00Z", ""));
+        cache.update(&event("M", "a-u-G", "2023-12-23T19:35:00Z", ""));
+
+        assert_eq!(cache.expire(datetime!(2023-12-23 19:20 UTC)), 0);
+        assert_eq!(cache.expire(datetime!(2023-12-23 19:31 UTC)), 1);
+        assert_eq!(
+            fresh_uids(&cache, datetime!(2023-12-23 19:20 UTC)),
+            vec!["M"]
+        );
+    }
+
+    #[test]
+    fn test_non_sa_events_are_not_cached() {
+        let mut cache = SaCache::default();
+        cache.update(&event("P", "t-x-c-t", "2023-12-23T19:30:00Z", ""));
+        cache.update(&event("C", "b-t-f", "2023-12-23T19:30:00Z", ""));
+        cache.update(&raw(r#"<event uid="N" type="a-u-G"/>"#));
+        assert!(fresh_uids(&cache, datetime!(2023-12-23 19:20 UTC)).is_empty());
+    }
+
+    #[test]
+    fn test_delete_and_cancel_evict_events() {
+        let mut cache = SaCache::default();
+        cache.update(&event("M", "a-u-G", "2023-12-23T19:30:00Z", ""));
+        cache.update(&raw(include_str!(
+            "../protocol/xml/fixtures/911_alert_start.xml"
+        )));
+        assert_eq!(fresh_uids(&cache, datetime!(2023-12-23 19:20 UTC)).len(), 2);
+
+        cache.update(&event(
+            "D",
+            "t-x-d-d",
+            "2023-12-23T19:30:00Z",
+            r#"<link uid="M" relation="none" type="none"/>"#,
+        ));
+        cache.update(&raw(include_str!(
+            "../protocol/xml/fixtures/911_deactive.xml"
+        )));
+        assert!(fresh_uids(&cache, datetime!(2023-12-23 19:20 UTC)).is_empty());
+    }
+}
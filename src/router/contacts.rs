@@ -0,0 +1,163 @@
+use crate::protocol::detail::{self, DetailElement, Group, Takv};
+use crate::protocol::{Destination, Message};
+use std::collections::{HashMap, HashSet};
+
+/// client as it announced itself in its own SA event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub connection_id: String,
+    pub uid: String,
+    pub callsign: String,
+    pub endpoint: Option<String>,
+    pub group: Option<Group>,
+    pub takv: Option<Takv>,
+}
+
+impl Contact {
+    /// markers and alerts carry `<contact>` too, but only client's own SA has endpoint or takv
+    pub fn from_self_sa(connection_id: &str, message: &Message) -> Option<Self> {
+        let uid = message.uid()?;
+        let detail = message.detail()?;
+        let contact = detail::Contact::find_in(detail)?;
+        let takv = Takv::find_in(detail);
+        if contact.endpoint.is_none() && takv.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            connection_id: connection_id.to_string(),
+            uid: uid.to_string(),
+            callsign: contact.callsign,
+            endpoint: contact.endpoint,
+            group: Group::find_in(detail),
+            takv,
+        })
+    }
+}
+
+/// maps connections to the contacts they represent
+#[derive(Default)]
+pub struct ContactRegistry {
+    by_connection: HashMap<String, Contact>,
+}
+
+impl ContactRegistry {
+    pub fn update(&mut self, contact: Contact) {
+        self.by_connection
+            .insert(contact.connection_id.clone(), contact);
+    }
+
+    pub fn remove(&mut self, connection_id: &str) -> Option<Contact> {
+        self.by_connection.remove(connection_id)
+    }
+
+    pub fn by_connection(&self, connection_id: &str) -> Option<&Contact> {
+        self.by_connection.get(connection_id)
+    }
+
+    pub fn by_uid(&self, uid: &str) -> Option<&Contact> {
+        self.by_connection
+            .values()
+            .find(|contact| contact.uid == uid)
+    }
+
+    pub fn by_callsign(&self, callsign: &str) -> Option<&Contact> {
+        self.by_connection
+            .values()
+            .find(|contact| contact.callsign == callsign)
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Contact> {
+        self.by_connection.values()
+    }
+
+    /// connection ids matching any of given destinations
+    pub fn resolve(&self, destinations: &[Destination]) -> HashSet<String> {
+        self.by_connection
+            .values()
+            .filter(|contact| {
+                destinations.iter().any(|destination| match destination {
+                    Destination::Callsign(callsign) => &contact.callsign == callsign,
+                    Destination::Uid(uid) => &contact.uid == uid,
+                })
+            })
+            .map(|contact| contact.connection_id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_contact_from_self_sa() -> anyhow::Result<()> {
+        let message =
+            Message::from_raw_xml(include_str!("../protocol/xml/fixtures/first_event.xml"))?;
+        let contact = Contact::from_self_sa("conn-1", &message).expect("contact present");
+        assert_eq!(
+            contact,
+            Contact {
+                connection_id: "conn-1".to_string(),
+                uid: "F6FD50A3-2827-4651-AFCC-2257F36B4C96".to_string(),
+                callsign: "Aaaaa".to_string(),
+                endpoint: Some("*:-1:stcp".to_string()),
+                group: Some(Group {
+                    name: "Cyan".to_string(),
+                    role: "Team Member".to_string()
+                }),
+                takv: Some(Takv {
+                    device: Some("iPhone".to_string()),
+                    platform: Some("iTAK".to_string()),
+                    os: Some("1.0.3".to_string()),
+                    version: Some("1.1.1.666".to_string()),
+                }),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alert_is_not_a_contact() -> anyhow::Result<()> {
+        let message =
+            Message::from_raw_xml(include_str!("../protocol/xml/fixtures/911_alert_start.xml"))?;
+        assert_eq!(Contact::from_self_sa("conn-1", &message), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_lookup_and_resolve() -> anyhow::Result<()> {
+        let mut registry = ContactRegistry::default();
+        for (connection_id, uid, callsign) in [("c1", "U1", "Alpha"), ("c2", "U2", "Bravo")] {
+            let message = Message::from_raw_xml(&format!(
+                r#"<event uid="{uid}"><detail><contact callsign="{callsign}" endpoint="*:-1:stcp"/></detail></event>"#
+            ))?;
+            registry.update(Contact::from_self_sa(connection_id, &message).expect("contact"));
+        }
+
+        assert_eq!(
+            registry.by_uid("U2").map(|c| c.callsign.as_str()),
+            Some("Bravo")
+        );
+        assert_eq!(
+            registry
+                .by_callsign("Alpha")
+                .map(|c| c.connection_id.as_str()),
+            Some("c1")
+        );
+        assert_eq!(
+            registry.resolve(&[
+                Destination::Callsign("Bravo".to_string()),
+                Destination::Uid("U1".to_string())
+            ]),
+            HashSet::from(["c1".to_string(), "c2".to_string()])
+        );
+
+        registry.remove("c1");
+        assert!(registry.by_uid("U1").is_none());
+        assert!(registry
+            .resolve(&[Destination::Uid("U1".to_string())])
+            .is_empty());
+        Ok(())
+    }
+}
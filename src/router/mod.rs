@@ -0,0 +1,671 @@
+mod cache;
+mod contacts;
+
+pub use contacts::Contact;
+
+use crate::{
+    buffered_channel::{self, BufferedReceiver, BufferedSender, OverflowPolicy, SendError},
+    connection::{CotClientConnection, Mode},
+    protocol::{Message, SharedMessage},
+    tls,
+};
+use cache::SaCache;
+use contacts::ContactRegistry;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Too many clients")]
+    TooManyClients,
+    #[error("Too many clients with common name: {0}")]
+    TooManyClientsForCommonName(String),
+    #[error("Too many clients from: {0}")]
+    TooManyClientsFromAddress(IpAddr),
+    #[error("Server is shutting down")]
+    ShuttingDown,
+}
+
+pub type RouterResult<T> = std::result::Result<T, Error>;
+
+/// limits of simultaneously connected clients
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_connections: usize,
+    pub max_per_common_name: Option<usize>,
+    pub max_per_ip: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_connections: 100,
+            max_per_common_name: None,
+            max_per_ip: None,
+        }
+    }
+}
+
+/// per connection queue of messages waiting to be written to the client
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboundQueueConfig {
+    pub size: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for OutboundQueueConfig {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            overflow_policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub connection_id: String,
+    pub queued: usize,
+    pub lagged: u64,
+}
+
+/// who is on the other side of a client connection, used for limits
+struct Peer {
+    /// none for connections without TLS
+    common_name: Option<String>,
+    remote_ip: IpAddr,
+}
+
+struct ConnectionEntry {
+    /// none for read-only connections, nothing is delivered to them
+    sender: Option<BufferedSender<SharedMessage>>,
+    /// none for outputs like multicast bridge, they do not count towards limits
+    peer: Option<Peer>,
+}
+
+#[derive(Clone)]
+pub struct Router {
+    limits: Limits,
+    outbound_queue: OutboundQueueConfig,
+    cn_counter_map: Arc<Mutex<HashMap<String, u64>>>,
+    connection_map: Arc<Mutex<HashMap<String, ConnectionEntry>>>,
+    contacts: Arc<Mutex<ContactRegistry>>,
+    sa_cache: Arc<Mutex<SaCache>>,
+    shutdown: CancellationToken,
+}
+
+impl Router {
+    pub fn new(limits: Limits, outbound_queue: OutboundQueueConfig) -> Self {
+        Self {
+            cn_counter_map: Default::default(),
+            connection_map: Default::default(),
+            contacts: Default::default(),
+            sa_cache: Default::default(),
+            shutdown: CancellationToken::new(),
+            limits,
+            outbound_queue,
+        }
+    }
+
+    pub fn new_cot_connection<T>(
+        &self,
+        stream: T,
+        tls_info: tls::Info,
+        remote_addr: SocketAddr,
+        mode: Mode,
+    ) -> RouterResult<CotClientConnection<T>> {
+        let cn_name = tls_info.common_name.as_deref().unwrap_or("unknown");
+        let peer = Peer {
+            common_name: Some(cn_name.to_string()),
+            remote_ip: remote_addr.ip(),
+        };
+        self.register_connection(stream, peer, mode, || {
+            let mut cn_map = self.cn_counter_map.lock().expect("cn counters locked");
+
+            let counter = cn_map.entry(cn_name.to_string()).or_default();
+            *counter += 1;
+            format!("{cn_name}-{counter}")
+        })
+    }
+
+    /// connection without TLS, identified by its address only
+    pub fn new_plain_connection<T>(
+        &self,
+        stream: T,
+        remote_addr: SocketAddr,
+        mode: Mode,
+    ) -> RouterResult<CotClientConnection<T>> {
+        let peer = Peer {
+            common_name: None,
+            remote_ip: remote_addr.ip(),
+        };
+        self.register_connection(stream, peer, mode, || format!("tcp-{remote_addr}"))
+    }
+
+    fn register_connection<T>(
+        &self,
+        stream: T,
+        peer: Peer,
+        mode: Mode,
+        connection_id: impl FnOnce() -> String,
+    ) -> RouterResult<CotClientConnection<T>> {
+        let mut connections = self.connection_map.lock().expect("connections locked");
+        if self.shutdown.is_cancelled() {
+            return Err(Error::ShuttingDown);
+        }
+        let peers = || connections.values().filter_map(|entry| entry.peer.as_ref());
+        if peers().count() >= self.limits.max_connections {
+            return Err(Error::TooManyClients);
+        }
+        if let (Some(max_per_common_name), Some(cn_name)) =
+            (self.limits.max_per_common_name, &peer.common_name)
+        {
+            let count = peers()
+                .filter(|other| other.common_name.as_ref() == Some(cn_name))
+                .count();
+            if count >= max_per_common_name {
+                return Err(Error::TooManyClientsForCommonName(cn_name.clone()));
+            }
+        }
+        if let Some(max_per_ip) = self.limits.max_per_ip {
+            let count = peers()
+                .filter(|other| other.remote_ip == peer.remote_ip)
+                .count();
+            if count >= max_per_ip {
+                return Err(Error::TooManyClientsFromAddress(peer.remote_ip));
+            }
+        }
+
+        let connection_id = connection_id();
+        info!("Connection: {connection_id}");
+
+        let (mut sender, outbound) = buffered_channel::channel_with_policy(
+            self.outbound_queue.size,
+            self.outbound_queue.overflow_policy,
+        );
+
+        // late joiner should see everybody right away, not after their next update
+        let writes = mode.direction.writes();
+        if writes {
+            let sa_cache = self.sa_cache.lock().expect("sa cache locked");
+            for message in sa_cache.fresh(OffsetDateTime::now_utc()) {
+                if !matches!(sender.send(message.clone()), Ok(true)) {
+                    warn!("Conn: {connection_id} SA cache replay did not fit into outbound queue");
+                    break;
+                }
+            }
+        }
+        connections.insert(
+            connection_id.clone(),
+            ConnectionEntry {
+                sender: writes.then_some(sender),
+                peer: Some(peer),
+            },
+        );
+
+        Ok(CotClientConnection::new(
+            stream,
+            connection_id,
+            mode,
+            outbound,
+            self.clone(),
+        ))
+    }
+
+    /// queue of everything routed to `output_id`, for outputs which are not client connections;
+    /// messages injected with the same id are not sent back, [`Router::connection_dropped`] detaches it
+    pub fn attach_output(&self, output_id: &str) -> BufferedReceiver<SharedMessage> {
+        let (sender, outbound) = buffered_channel::channel_with_policy(
+            self.outbound_queue.size,
+            self.outbound_queue.overflow_policy,
+        );
+        info!("Output: {output_id}");
+        let mut connections = self.connection_map.lock().expect("connections locked");
+        // after shutdown the queue is closed right away
+        let sender = (!self.shutdown.is_cancelled()).then_some(sender);
+        connections.insert(
+            output_id.to_string(),
+            ConnectionEntry { sender, peer: None },
+        );
+        outbound
+    }
+
+    /// delivers message to its marti destinations or, if there are none,
+    /// fans it out to every other connected client
+    pub fn cot_packet_received(
+        &self,
+        connection_id: &String,
+        message: Message,
+    ) -> RouterResult<()> {
+        debug!("Conn: {connection_id} sent: ${message:#?}");
+        if let Some(contact) = Contact::from_self_sa(connection_id, &message) {
+            self.contacts
+                .lock()
+                .expect("contacts locked")
+                .update(contact);
+        }
+
+        self.route(connection_id, message);
+        Ok(())
+    }
+
+    /// message from input which can not be written to, like UDP listener;
+    /// it is routed the same way, but its sender never becomes a contact
+    pub fn input_packet_received(&self, input_id: &String, message: Message) {
+        debug!("Input: {input_id} sent: ${message:#?}");
+        self.route(input_id, message);
+    }
+
+    fn route(&self, source_id: &String, message: Message) {
+        // encoded at most once per wire format, however many receivers there are
+        let message = SharedMessage::new(message);
+        let destinations = message.destinations();
+        let targets = if destinations.is_empty() {
+            self.sa_cache
+                .lock()
+                .expect("sa cache locked")
+                .update(&message);
+            None
+        } else {
+            let targets = self
+                .contacts
+                .lock()
+                .expect("contacts locked")
+                .resolve(&destinations);
+            if targets.is_empty() {
+                debug!("Conn: {source_id} no connections for: {destinations:?}");
+            }
+            Some(targets)
+        };
+
+        self.deliver(Some(source_id), &message, targets.as_ref());
+    }
+
+    /// sends message to every connection except the source, or only to `targets` when present
+    fn deliver(
+        &self,
+        source_id: Option<&String>,
+        message: &SharedMessage,
+        targets: Option<&HashSet<String>>,
+    ) {
+        let mut connections = self.connection_map.lock().expect("connections locked");
+        connections.retain(|receiver_id, ConnectionEntry { sender, .. }| {
+            let Some(sender) = sender else {
+                return true;
+            };
+            if Some(receiver_id) == source_id {
+                return true;
+            }
+            if matches!(targets, Some(targets) if !targets.contains(receiver_id)) {
+                return true;
+            }
+            match sender.send(message.clone()) {
+                Ok(true) => true,
+                Ok(false) => {
+                    warn!(
+                        "Conn: {receiver_id} is lagging, dropped messages: {}",
+                        sender.lagged()
+                    );
+                    true
+                }
+                Err(SendError::Closed) => {
+                    debug!("Conn: {receiver_id} is not receiving anymore");
+                    true
+                }
+                Err(SendError::Lagged(lagged)) => {
+                    warn!("Conn: {receiver_id} lagged behind by {lagged} messages, disconnecting");
+                    false
+                }
+            }
+        });
+    }
+
+    /// periodically evicts stale events from SA cache, never returns
+    pub async fn run_stale_sweeper(self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let expired = self
+                .sa_cache
+                .lock()
+                .expect("sa cache locked")
+                .expire(OffsetDateTime::now_utc());
+            if expired > 0 {
+                debug!("Expired {expired} stale events");
+            }
+        }
+    }
+
+    pub fn contact_by_uid(&self, uid: &str) -> Option<Contact> {
+        let contacts = self.contacts.lock().expect("contacts locked");
+        contacts.by_uid(uid).cloned()
+    }
+
+    pub fn contact_by_callsign(&self, callsign: &str) -> Option<Contact> {
+        let contacts = self.contacts.lock().expect("contacts locked");
+        contacts.by_callsign(callsign).cloned()
+    }
+
+    pub fn contact_by_connection(&self, connection_id: &str) -> Option<Contact> {
+        let contacts = self.contacts.lock().expect("contacts locked");
+        contacts.by_connection(connection_id).cloned()
+    }
+
+    pub fn contacts(&self) -> Vec<Contact> {
+        let contacts = self.contacts.lock().expect("contacts locked");
+        contacts.all().cloned().collect()
+    }
+
+    pub fn connection_stats(&self) -> Vec<ConnectionStats> {
+        let connections = self.connection_map.lock().expect("connections locked");
+        connections
+            .iter()
+            .filter_map(|(connection_id, entry)| {
+                let sender = entry.sender.as_ref()?;
+                Some(ConnectionStats {
+                    connection_id: connection_id.clone(),
+                    queued: sender.queued(),
+                    lagged: sender.lagged(),
+                })
+            })
+            .collect()
+    }
+
+    /// stops routing and closes every outbound queue, connections send what is already queued
+    /// and finish, new ones are refused
+    pub fn shutdown(&self) {
+        let mut connections = self.connection_map.lock().expect("connections locked");
+        self.shutdown.cancel();
+        for entry in connections.values_mut() {
+            entry.sender = None;
+        }
+    }
+
+    /// completes once [`Router::shutdown`] is called
+    pub async fn shutting_down(&self) {
+        self.shutdown.cancelled().await
+    }
+
+    pub fn connection_dropped(&self, connection_id: &String) {
+        info!("Connection closed: {connection_id}");
+        self.connection_map
+            .lock()
+            .expect("connections locked")
+            .remove(connection_id);
+        let removed = {
+            let mut contacts = self.contacts.lock().expect("contacts locked");
+            contacts.remove(connection_id).map(|contact| {
+                let reconnected = contacts.by_uid(&contact.uid).is_some();
+                (contact, reconnected)
+            })
+        };
+        match removed {
+            Some((contact, true)) => {
+                info!(
+                    "Contact {} ({}) still connected elsewhere",
+                    contact.callsign, contact.uid
+                );
+            }
+            Some((contact, false)) => {
+                info!("Contact gone: {} ({})", contact.callsign, contact.uid);
+                // without it other clients keep showing the contact until its SA goes stale
+                self.sa_cache
+                    .lock()
+                    .expect("sa cache locked")
+                    .remove(&contact.uid);
+                let delete = Message::delete_event(&contact.uid, OffsetDateTime::now_utc());
+                self.deliver(None, &delete.into(), None);
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tls_info(common_name: &str) -> tls::Info {
+        tls::Info {
+            subject: format!("CN={common_name}"),
+            common_name: Some(common_name.to_string()),
+            serial: "1".to_string(),
+        }
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().expect("valid socket addr")
+    }
+
+    fn read_only() -> Mode {
+        Mode {
+            direction: crate::connection::Direction::ReadOnly,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_slot_is_released_on_drop() {
+        let router = Router::new(
+            Limits {
+                max_connections: 1,
+                ..Default::default()
+            },
+            Default::default(),
+        );
+        let conn = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1000"), Default::default())
+            .expect("first connection");
+        assert!(matches!(
+            router.new_cot_connection((), tls_info("B"), addr("10.0.0.2:1000"), Default::default()),
+            Err(Error::TooManyClients)
+        ));
+
+        router.connection_dropped(&conn.connection_id().to_string());
+        assert!(router
+            .new_cot_connection((), tls_info("B"), addr("10.0.0.2:1000"), Default::default())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_per_common_name_limit() {
+        let router = Router::new(
+            Limits {
+                max_per_common_name: Some(1),
+                ..Default::default()
+            },
+            Default::default(),
+        );
+        let _conn = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1000"), Default::default())
+            .expect("first connection");
+        assert!(matches!(
+            router.new_cot_connection((), tls_info("A"), addr("10.0.0.2:1000"), Default::default()),
+            Err(Error::TooManyClientsForCommonName(cn)) if cn == "A"
+        ));
+        assert!(router
+            .new_cot_connection((), tls_info("B"), addr("10.0.0.2:1000"), Default::default())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_per_ip_limit() {
+        let router = Router::new(
+            Limits {
+                max_per_ip: Some(1),
+                ..Default::default()
+            },
+            Default::default(),
+        );
+        let _conn = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1000"), Default::default())
+            .expect("first connection");
+        assert!(matches!(
+            router.new_cot_connection((), tls_info("B"), addr("10.0.0.1:1001"), Default::default()),
+            Err(Error::TooManyClientsFromAddress(ip)) if ip == addr("10.0.0.1:0").ip()
+        ));
+        assert!(router
+            .new_cot_connection((), tls_info("B"), addr("10.0.0.2:1000"), Default::default())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_output_does_not_count_and_gets_no_echo() -> anyhow::Result<()> {
+        let router = Router::new(
+            Limits {
+                max_connections: 1,
+                ..Default::default()
+            },
+            Default::default(),
+        );
+        let mut output = router.attach_output("bridge");
+        let conn = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1000"), Default::default())
+            .expect("output is not a client");
+
+        router.input_packet_received(
+            &"bridge".to_string(),
+            Message::from_raw_xml(r#"<event uid="mesh"/>"#)?,
+        );
+        router.cot_packet_received(
+            &conn.connection_id().to_string(),
+            Message::from_raw_xml(r#"<event uid="client"/>"#)?,
+        )?;
+        let received = output.read_next().await.expect("message routed");
+        assert_eq!(received.uid(), Some("client"));
+
+        router.connection_dropped(&"bridge".to_string());
+        assert!(output.read_next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_shares_encoded_frames() -> anyhow::Result<()> {
+        let router = Router::new(Default::default(), Default::default());
+        let mut first = router.attach_output("first");
+        let mut second = router.attach_output("second");
+        router.input_packet_received(
+            &"udp".to_string(),
+            Message::from_raw_xml(include_str!("../protocol/xml/fixtures/first_event.xml"))?,
+        );
+
+        let first = first.read_next().await.expect("message routed");
+        let second = second.read_next().await.expect("message routed");
+        assert_eq!(first.xml()?.as_ptr(), second.xml()?.as_ptr());
+        assert_eq!(
+            first.proto_payload()?.as_ptr(),
+            second.proto_payload()?.as_ptr()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconnected_contact_is_not_deleted_by_old_connection() -> anyhow::Result<()> {
+        let router = Router::new(Default::default(), Default::default());
+        let mut observer = router.attach_output("observer");
+        let self_sa = || {
+            Message::from_raw_xml(concat!(
+                r#"<event uid="U1" type="a-f-G-U-C" stale="2100-01-01T00:00:00Z">"#,
+                r#"<detail><contact callsign="Alpha" endpoint="*:-1:stcp"/></detail></event>"#
+            ))
+        };
+        let old = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1000"), Default::default())
+            .expect("old connection");
+        let new = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1001"), Default::default())
+            .expect("new connection");
+        router.cot_packet_received(&old.connection_id().to_string(), self_sa()?)?;
+        router.cot_packet_received(&new.connection_id().to_string(), self_sa()?)?;
+        for _ in 0..2 {
+            let sa = observer.read_next().await.expect("sa routed");
+            assert_eq!(sa.uid(), Some("U1"));
+        }
+
+        router.connection_dropped(&old.connection_id().to_string());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), observer.read_next())
+                .await
+                .is_err(),
+            "no delete while contact is connected"
+        );
+        assert_eq!(
+            router.contact_by_uid("U1").map(|c| c.connection_id),
+            Some(new.connection_id().to_string())
+        );
+        let late = router
+            .new_cot_connection((), tls_info("B"), addr("10.0.0.2:1000"), Default::default())
+            .expect("late joiner");
+        let stats = router.connection_stats();
+        let late_stats = stats
+            .iter()
+            .find(|stats| stats.connection_id == late.connection_id())
+            .expect("late joiner stats");
+        assert_eq!(late_stats.queued, 1, "SA stays cached for late joiners");
+
+        router.connection_dropped(&new.connection_id().to_string());
+        let delete = observer.read_next().await.expect("delete routed");
+        assert_eq!(delete.event_type(), Some("t-x-d-d"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plain_connections() -> anyhow::Result<()> {
+        let router = Router::new(
+            Limits {
+                max_per_common_name: Some(1),
+                ..Default::default()
+            },
+            Default::default(),
+        );
+        let read_only = router
+            .new_plain_connection((), addr("10.0.0.1:1000"), read_only())
+            .expect("no common name to limit");
+        let read_write = router
+            .new_plain_connection((), addr("10.0.0.1:1001"), Default::default())
+            .expect("no common name to limit");
+        assert_eq!(read_only.connection_id(), "tcp-10.0.0.1:1000");
+
+        router.cot_packet_received(
+            &read_write.connection_id().to_string(),
+            Message::from_raw_xml(r#"<event uid="client"/>"#)?,
+        )?;
+        let stats = router.connection_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].connection_id, read_write.connection_id());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_queues_after_queued_messages() -> anyhow::Result<()> {
+        let router = Router::new(Default::default(), Default::default());
+        let mut output = router.attach_output("bridge");
+        let conn = router
+            .new_cot_connection((), tls_info("A"), addr("10.0.0.1:1000"), Default::default())
+            .expect("first connection");
+        router.cot_packet_received(
+            &conn.connection_id().to_string(),
+            Message::from_raw_xml(r#"<event uid="client"/>"#)?,
+        )?;
+
+        router.shutdown();
+        router.shutting_down().await;
+        let received = output.read_next().await.expect("queued message kept");
+        assert_eq!(received.uid(), Some("client"));
+        assert!(output.read_next().await.is_none());
+        assert!(matches!(
+            router.new_cot_connection((), tls_info("B"), addr("10.0.0.2:1000"), Default::default()),
+            Err(Error::ShuttingDown)
+        ));
+        Ok(())
+    }
+}
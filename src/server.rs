@@ -1,96 +1,335 @@
-use crate::router::Router;
-use crate::tls;
+use crate::connection::{Direction, FrameLimits, Mode, Protocol, Timeouts};
+use crate::protocol::xml::XmlLimits;
+use crate::rate_limit::RateLimit;
+use crate::router::{Limits, OutboundQueueConfig, Router};
+use crate::{multicast, tcp, tls, udp};
 use anyhow::{anyhow, Context};
+use socket2::{Domain, Socket, Type};
 use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::select;
+use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, info, info_span, Instrument};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use x509_parser::nom::AsBytes;
 use x509_parser::prelude::FromDer;
 
+const STALE_SWEEP_PERIOD: Duration = Duration::from_secs(1);
+const LISTEN_BACKLOG: i32 = 1024;
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 async fn check_for_error(fut: impl Future<Output = anyhow::Result<()>>) {
     if let Err(err) = fut.await {
         error!("Client conn error: {err:?}")
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// mutual TLS, with its own certificates or the server wide ones
+    Tls(Option<tls::Config>),
+    Tcp,
+    Udp(RateLimit),
+}
+
+/// single listening socket
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub bind: SocketAddr,
+    /// for IPv6 `bind` refuses IPv4 clients, otherwise socket is dual-stack
+    pub ipv6_only: bool,
+    pub transport: Transport,
+    /// ignored by UDP, datagrams of both protocols are accepted
+    pub protocol: Protocol,
+    pub direction: Direction,
+    /// ignored by UDP, malformed datagrams are always skipped
+    pub lenient_xml: bool,
+}
+
+impl Input {
+    fn new(bind: SocketAddr, transport: Transport) -> Self {
+        Self {
+            bind,
+            ipv6_only: false,
+            transport,
+            protocol: Default::default(),
+            direction: Default::default(),
+            lenient_xml: false,
+        }
+    }
+
+    /// mTLS streaming, conventionally port 8089
+    pub fn tls(bind: SocketAddr) -> Self {
+        Self::new(bind, Transport::Tls(None))
+    }
+
+    /// unauthenticated streaming, conventionally port 8087
+    pub fn tcp(bind: SocketAddr) -> Self {
+        Self::new(bind, Transport::Tcp)
+    }
+
+    /// unauthenticated datagrams, conventionally port 8087
+    pub fn udp(bind: SocketAddr) -> Self {
+        Self::new(bind, Transport::Udp(Default::default()))
+    }
+
+    fn mode(&self, timeouts: Timeouts, xml_limits: XmlLimits, frame_limits: FrameLimits) -> Mode {
+        Mode {
+            direction: self.direction,
+            protocol: self.protocol,
+            lenient_xml: self.lenient_xml,
+            xml_limits,
+            frame_limits,
+            timeouts,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Config {
-    pub listen_port: u16,
-    pub tls: tls::Config,
+    pub inputs: Vec<Input>,
+    /// certificates of TLS inputs which do not have their own
+    pub tls: Option<tls::Config>,
+    pub limits: Limits,
+    pub outbound_queue: OutboundQueueConfig,
+    pub mesh_bridge: Option<multicast::Config>,
+    pub timeouts: Timeouts,
+    /// checked before every xml event from clients, UDP and mesh is parsed
+    pub xml_limits: XmlLimits,
+    /// largest frames read from streaming clients
+    pub frame_limits: FrameLimits,
+    /// how long connections may take to send their queued messages on shutdown
+    pub shutdown_grace_period: Duration,
 }
 
 pub struct Server {
-    tls_acceptor: TlsAcceptor,
-    socket_addr: (&'static str, u16),
+    /// each input with its acceptor, if it is a TLS one
+    inputs: Vec<(Input, Option<TlsAcceptor>)>,
+    mesh_bridge: Option<multicast::Config>,
     router: Router,
+    timeouts: Timeouts,
+    xml_limits: XmlLimits,
+    frame_limits: FrameLimits,
+    shutdown: CancellationToken,
+    shutdown_grace_period: Duration,
 }
 
 impl Server {
     pub fn new(config: Config) -> anyhow::Result<Self> {
-        let tls_config = Arc::new(tls::setup_server_tls(config.tls)?);
-        let tls_acceptor = TlsAcceptor::from(tls_config);
+        if config.inputs.is_empty() {
+            return Err(anyhow!("at least one input expected"));
+        }
+        let mut default_acceptor = None;
+        let mut inputs = Vec::with_capacity(config.inputs.len());
+        for input in config.inputs {
+            let acceptor = match &input.transport {
+                Transport::Tls(Some(tls)) => Some(tls_acceptor(tls.clone())?),
+                Transport::Tls(None) => {
+                    if default_acceptor.is_none() {
+                        let tls = config.tls.clone().ok_or_else(|| {
+                            anyhow!("TLS input {} without certificates", input.bind)
+                        })?;
+                        default_acceptor = Some(tls_acceptor(tls)?);
+                    }
+                    default_acceptor.clone()
+                }
+                Transport::Tcp | Transport::Udp(_) => None,
+            };
+            inputs.push((input, acceptor));
+        }
+
         Ok(Self {
-            tls_acceptor,
-            socket_addr: ("0.0.0.0", config.listen_port),
-            router: Router::new(100),
+            inputs,
+            mesh_bridge: config.mesh_bridge,
+            router: Router::new(config.limits, config.outbound_queue),
+            timeouts: config.timeouts,
+            xml_limits: config.xml_limits,
+            frame_limits: config.frame_limits,
+            shutdown: CancellationToken::new(),
+            shutdown_grace_period: config.shutdown_grace_period,
         })
     }
 
+    /// cancelling the token makes [`Server::run`] shut down gracefully
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// runs every input until shutdown is requested or one of them fails;
+    /// either way inputs stop accepting and connections get the grace period to drain
     pub async fn run(self) -> anyhow::Result<()> {
-        let listener = TcpListener::bind(self.socket_addr).await?;
+        let connections = TaskTracker::new();
+        let mut listeners = JoinSet::new();
+        let sweeper = self.router.clone().run_stale_sweeper(STALE_SWEEP_PERIOD);
+        listeners.spawn(async move {
+            sweeper.await;
+            Ok(())
+        });
 
-        self.handle_cot_connections(listener).await?;
+        for (input, acceptor) in self.inputs {
+            let router = self.router.clone();
+            let mode = input.mode(self.timeouts, self.xml_limits, self.frame_limits);
+            match (input.transport, acceptor) {
+                (Transport::Tls(_), Some(acceptor)) => {
+                    let listener = bind_tcp(input.bind, input.ipv6_only)?;
+                    listeners.spawn(handle_cot_connections(
+                        listener,
+                        acceptor,
+                        mode,
+                        router,
+                        connections.clone(),
+                    ));
+                }
+                (Transport::Tcp, _) => {
+                    let listener = bind_tcp(input.bind, input.ipv6_only)?;
+                    listeners.spawn(tcp::run_input(listener, mode, router, connections.clone()));
+                }
+                (Transport::Udp(rate_limit), _) => {
+                    let socket = bind_udp(input.bind, input.ipv6_only)?;
+                    listeners.spawn(udp::run_input(socket, rate_limit, self.xml_limits, router));
+                }
+                (Transport::Tls(_), None) => unreachable!("TLS acceptors are created by new"),
+            }
+        }
 
-        Ok(())
-    }
+        if let Some(mesh_bridge) = self.mesh_bridge {
+            let socket = multicast::bind(&mesh_bridge)?;
+            let router = self.router.clone();
+            let xml_limits = self.xml_limits;
+            // detached by router shutdown like any other connection
+            connections.spawn(async move {
+                if let Err(err) =
+                    multicast::run_bridge(socket, mesh_bridge, xml_limits, router).await
+                {
+                    error!("Mesh bridge error: {err:?}")
+                }
+            });
+        }
 
-    async fn handle_cot_connections(&self, listener: TcpListener) -> anyhow::Result<()> {
+        let res = select! {
+            _ = self.shutdown.cancelled() => Ok(()),
+            res = first_failure(&mut listeners) => res,
+        };
         info!(
-            "Listening for COT on: {}",
-            listener.local_addr().expect("local addr")
+            "Shutting down, waiting up to {:?} for connections",
+            self.shutdown_grace_period
         );
-        loop {
-            let (stream, socket) = listener.accept().await?;
-            info!("Connection from: {socket:?}");
-            let tls_acceptor = self.tls_acceptor.clone();
-            let router = self.router.clone();
-            let conn_span = info_span!("COT client connection", remote_sock = ?socket);
-
-            tokio::spawn(
-                check_for_error(async move {
-                    let stream = tls_acceptor.accept(stream).await.context("TLS accept")?;
-                    let (_, server_conn) = stream.get_ref();
-
-                    // accept future completion means peer certificates should be filled
-                    let peer_cert_chain = server_conn
-                        .peer_certificates()
-                        .ok_or_else(|| anyhow!("client cert chain expected"))?;
-                    let peer_cert = peer_cert_chain
-                        .first()
-                        .ok_or_else(|| anyhow!("at least 1 client cert expected"))?;
-
-                    let (_, peer_x509_cert) =
-                        x509_parser::certificate::X509Certificate::from_der(peer_cert.as_bytes())?;
-
-                    debug!("Peer certificate: {peer_x509_cert:#?}");
-
-                    let tls_info: tls::Info = peer_x509_cert.into();
-                    let secured_conn_span = info_span!(
-                        "tls",
-                        subject = tls_info.common_name,
-                        serial = tls_info.serial
-                    );
-
-                    router
-                        .new_cot_connection(stream, tls_info)?
-                        .conn_loop()
-                        .instrument(secured_conn_span)
-                        .await?;
-                    Ok(())
-                })
-                .instrument(conn_span),
+        listeners.shutdown().await;
+        self.router.shutdown();
+        connections.close();
+        if tokio::time::timeout(self.shutdown_grace_period, connections.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} connections still open after grace period",
+                connections.len()
             );
+        } else {
+            info!("All connections closed");
         }
+        res
+    }
+}
+
+async fn first_failure(listeners: &mut JoinSet<anyhow::Result<()>>) -> anyhow::Result<()> {
+    while let Some(res) = listeners.join_next().await {
+        res.context("listener task")??;
+    }
+    Ok(())
+}
+
+fn tls_acceptor(config: tls::Config) -> anyhow::Result<TlsAcceptor> {
+    Ok(TlsAcceptor::from(Arc::new(tls::setup_server_tls(config)?)))
+}
+
+fn new_socket(addr: SocketAddr, ipv6_only: bool, socket_type: Type) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), socket_type, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn bind_tcp(addr: SocketAddr, ipv6_only: bool) -> io::Result<TcpListener> {
+    let socket = new_socket(addr, ipv6_only, Type::STREAM)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+fn bind_udp(addr: SocketAddr, ipv6_only: bool) -> io::Result<UdpSocket> {
+    let socket = new_socket(addr, ipv6_only, Type::DGRAM)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+async fn handle_cot_connections(
+    listener: TcpListener,
+    tls_acceptor: TlsAcceptor,
+    mode: Mode,
+    router: Router,
+    connections: TaskTracker,
+) -> anyhow::Result<()> {
+    info!(
+        "Listening for COT on: {} ({mode:?})",
+        listener.local_addr()?
+    );
+    loop {
+        let (stream, socket) = listener.accept().await?;
+        info!("Connection from: {socket:?}");
+        let tls_acceptor = tls_acceptor.clone();
+        let router = router.clone();
+        let conn_span = info_span!("COT client connection", remote_sock = ?socket);
+
+        connections.spawn(
+            check_for_error(async move {
+                let accept = tls_acceptor.accept(stream);
+                let stream = match mode.timeouts.tls_handshake {
+                    Some(timeout) => tokio::time::timeout(timeout, accept)
+                        .await
+                        .map_err(|_| anyhow!("TLS handshake not completed within {timeout:?}"))?,
+                    None => accept.await,
+                }
+                .context("TLS accept")?;
+                let (_, server_conn) = stream.get_ref();
+
+                // accept future completion means peer certificates should be filled
+                let peer_cert_chain = server_conn
+                    .peer_certificates()
+                    .ok_or_else(|| anyhow!("client cert chain expected"))?;
+                let peer_cert = peer_cert_chain
+                    .first()
+                    .ok_or_else(|| anyhow!("at least 1 client cert expected"))?;
+
+                let (_, peer_x509_cert) =
+                    x509_parser::certificate::X509Certificate::from_der(peer_cert.as_bytes())?;
+
+                debug!("Peer certificate: {peer_x509_cert:#?}");
+
+                let tls_info: tls::Info = peer_x509_cert.into();
+                let secured_conn_span = info_span!(
+                    "tls",
+                    subject = tls_info.common_name,
+                    serial = tls_info.serial
+                );
+
+                router
+                    .new_cot_connection(stream, tls_info, socket, mode)?
+                    .conn_loop()
+                    .instrument(secured_conn_span)
+                    .await?;
+                Ok(())
+            })
+            .instrument(conn_span),
+        );
     }
 }
@@ -0,0 +1,39 @@
+use crate::connection::Mode;
+use crate::router::Router;
+use anyhow::Context;
+use tokio::net::TcpListener;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, info_span, Instrument};
+
+/// accepts classic unauthenticated streaming CoT, only for trusted networks;
+/// returns only if listener fails, connection tasks are spawned on `connections`
+pub async fn run_input(
+    listener: TcpListener,
+    mode: Mode,
+    router: Router,
+    connections: TaskTracker,
+) -> anyhow::Result<()> {
+    info!(
+        "Listening for plain COT on: {} ({mode:?})",
+        listener.local_addr()?
+    );
+    loop {
+        let (stream, socket) = listener.accept().await.context("TCP accept")?;
+        info!("Plain connection from: {socket:?}");
+        let router = router.clone();
+        let conn_span = info_span!("COT plain connection", remote_sock = ?socket);
+
+        connections.spawn(
+            async move {
+                let res = match router.new_plain_connection(stream, socket, mode) {
+                    Ok(conn) => conn.conn_loop().await,
+                    Err(err) => Err(err.into()),
+                };
+                if let Err(err) = res {
+                    error!("Client conn error: {err:?}")
+                }
+            }
+            .instrument(conn_span),
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use crate::protocol::mesh::{MeshCodec, MeshEncoding};
+use crate::protocol::xml::XmlLimits;
+use crate::rate_limit::{RateLimit, TokenBucket};
+use crate::router::Router;
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::Decoder;
+use tracing::{debug, info, warn};
+
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+
+/// plain UDP CoT input used by sensors and legacy tools, both xml and TAK protocol datagrams
+/// are accepted; routes every datagram as if it was sent by pseudo-connection `udp-{port}`,
+/// returns only if socket fails
+pub async fn run_input(
+    socket: UdpSocket,
+    rate_limit: RateLimit,
+    xml_limits: XmlLimits,
+    router: Router,
+) -> anyhow::Result<()> {
+    let local_addr = socket.local_addr()?;
+    let input_id = format!("udp-{}", local_addr.port());
+    info!("Listening for UDP COT on: {local_addr}");
+
+    let mut codec = MeshCodec::new(MeshEncoding::Xml).with_xml_limits(xml_limits);
+    let mut bucket = TokenBucket::new(rate_limit, Instant::now());
+    let mut dropped = 0u64;
+    let mut recv_buff = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, sender) = socket.recv_from(&mut recv_buff).await?;
+        // flood must not cost a parse per datagram, so tokens are taken before decoding
+        if !bucket.try_acquire(Instant::now()) {
+            dropped += 1;
+            // flooding source would flood the log as well
+            if dropped.is_power_of_two() {
+                warn!("Input: {input_id} over rate limit, dropped {dropped} datagrams");
+            }
+            continue;
+        }
+        debug!("Input: {input_id} datagram from: {sender}");
+        match codec.decode(&mut BytesMut::from(&recv_buff[..len])) {
+            Ok(Some(message)) => router.input_packet_received(&input_id, message),
+            Ok(None) => {}
+            Err(e) => warn!("Input: {input_id} invalid datagram from {sender}: {e}"),
+        }
+    }
+}
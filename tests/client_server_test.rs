@@ -1,37 +1,96 @@
 mod test_client;
 
+use futures::{SinkExt, StreamExt};
+use prost::Message as _;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
 use std::time::Duration;
+use tak_rs::connection::{Direction, Protocol, Timeouts};
+use tak_rs::protocol::mesh::{MeshEncoding, MESH_HEADER};
+use tak_rs::protocol::proto::TakProtoCodec;
+use tak_rs::protocol::xml::{CotLegacyCodec, XmlLimits};
 use tak_rs::protocol::Message;
-use tak_rs::server::{Config, Server};
-use tak_rs::tls;
+use tak_rs::rate_limit::RateLimit;
+use tak_rs::server::{Config, Input, Server, Transport};
+use tak_rs::{multicast, tls};
+use test_client::TestClient;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_util::codec::Framed;
 use tracing::info;
 use tracing::metadata::LevelFilter;
 
 const TEST_PORT: u16 = 13000;
-#[tokio::test]
-async fn test_client_sends_message_to_server() -> anyhow::Result<()> {
-    tak_rs::tracing::init(LevelFilter::INFO)?;
-
-    let _server_task = tokio::spawn(async {
-        let server = Server::new(Config {
-            listen_port: TEST_PORT,
-            tls: tls::Config {
-                ca: "tests/certs/ca.crt".to_string(),
-                cert: "tests/certs/server.crt".to_string(),
-                key: "tests/certs/server.key".to_string(),
-            },
-        })?;
+const DIRECTED_TEST_PORT: u16 = 13001;
+const SA_CACHE_TEST_PORT: u16 = 13002;
+const CONTACT_DELETE_TEST_PORT: u16 = 13003;
+const PROTO_NEGOTIATION_TEST_PORT: u16 = 13004;
+const UDP_INPUT_TEST_PORT: u16 = 13005;
+const MESH_BRIDGE_TEST_PORT: u16 = 13006;
+const PLAIN_TCP_TEST_PORT: u16 = 13007;
+const PLAIN_TCP_INPUT_PORT: u16 = 13008;
+const MULTI_INPUT_TLS_PORT: u16 = 13009;
+const MULTI_INPUT_PROTO_PORT: u16 = 13010;
+const MULTI_INPUT_XML_PORT: u16 = 13011;
+const SHUTDOWN_TEST_PORT: u16 = 13012;
+const SHUTDOWN_PLAIN_TCP_PORT: u16 = 13013;
+const HANDSHAKE_TIMEOUT_TEST_PORT: u16 = 13014;
+const XML_DETAIL_LIMITS_TEST_PORT: u16 = 13015;
+const XML_DETAIL_LIMITS_PROTO_PORT: u16 = 13016;
+
+fn init_tracing() {
+    // every test in this binary shares a single global subscriber
+    let _ = tak_rs::tracing::init(LevelFilter::INFO);
+}
+
+fn any_addr(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+}
+
+fn test_config(port: u16) -> Config {
+    Config {
+        inputs: vec![Input::tls(any_addr(port))],
+        tls: Some(tls::Config {
+            ca: "tests/certs/ca.crt".to_string(),
+            cert: "tests/certs/server.crt".to_string(),
+            key: "tests/certs/server.key".to_string(),
+        }),
+        limits: Default::default(),
+        outbound_queue: Default::default(),
+        mesh_bridge: None,
+        timeouts: Default::default(),
+        xml_limits: Default::default(),
+        frame_limits: Default::default(),
+        shutdown_grace_period: Duration::from_secs(1),
+    }
+}
+
+fn spawn_server_with(config: Config) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    tokio::spawn(async move {
+        let server = Server::new(config)?;
 
         server.run().await
-    });
+    })
+}
 
-    let mut client_a = test_client::TestClient::setup("client_a", "localhost", TEST_PORT).await?;
+fn spawn_server(port: u16) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    spawn_server_with(test_config(port))
+}
 
-    let mut client_b = test_client::TestClient::setup("client_b", "localhost", TEST_PORT).await?;
+fn as_xml_string(msg: &Message) -> anyhow::Result<String> {
+    let mut buff = Vec::new();
+    msg.as_xml(&mut buff)?;
+    Ok(String::from_utf8(buff)?)
+}
 
-    client_a
-        .send_raw(b"<event><abc>From client A</abc></event>")
-        .await?;
+#[tokio::test]
+async fn test_client_sends_message_to_server() -> anyhow::Result<()> {
+    init_tracing();
+
+    let _server_task = spawn_server(TEST_PORT);
+
+    let mut client_a = TestClient::setup("client_a", "localhost", TEST_PORT).await?;
+
+    let mut client_b = TestClient::setup("client_b", "localhost", TEST_PORT).await?;
 
     client_b
         .send(Message::from_raw_xml(
@@ -39,8 +98,21 @@ async fn test_client_sends_message_to_server() -> anyhow::Result<()> {
         )?)
         .await?;
 
-    // no implementation yet
-    // let _msg = client_b.expect_message().await?;
+    let msg = client_a.expect_message().await?;
+    assert_eq!(
+        as_xml_string(&msg)?,
+        "<event><abc>From client B</abc></event>"
+    );
+
+    client_a
+        .send_raw(b"<event><abc>From client A</abc></event>")
+        .await?;
+
+    let msg = client_b.expect_message().await?;
+    assert_eq!(
+        as_xml_string(&msg)?,
+        "<event><abc>From client A</abc></event>"
+    );
 
     client_a.shutdown().await?;
     client_b.shutdown().await?;
@@ -48,3 +120,456 @@ async fn test_client_sends_message_to_server() -> anyhow::Result<()> {
     tokio::time::sleep(Duration::from_secs(1)).await;
     Ok(())
 }
+
+fn self_sa(uid: &str, callsign: &str) -> String {
+    format!(
+        r#"<event uid="{uid}" type="a-f-G-U-C"><detail><contact callsign="{callsign}" endpoint="*:-1:stcp"/></detail></event>"#
+    )
+}
+
+#[tokio::test]
+async fn test_directed_messages_reach_only_destinations() -> anyhow::Result<()> {
+    init_tracing();
+
+    let _server_task = spawn_server(DIRECTED_TEST_PORT);
+
+    let mut alpha = TestClient::setup("client_a", "localhost", DIRECTED_TEST_PORT).await?;
+    let mut bravo = TestClient::setup("client_b", "localhost", DIRECTED_TEST_PORT).await?;
+    let mut charlie = TestClient::setup("client_b", "localhost", DIRECTED_TEST_PORT).await?;
+
+    // last connected client announces itself first, so everyone is surely registered
+    charlie.send_raw(self_sa("C", "Charlie").as_bytes()).await?;
+    alpha.expect_message().await?;
+    bravo.expect_message().await?;
+
+    bravo.send_raw(self_sa("B", "Bravo").as_bytes()).await?;
+    alpha.expect_message().await?;
+    charlie.expect_message().await?;
+
+    alpha.send_raw(self_sa("A", "Alpha").as_bytes()).await?;
+    bravo.expect_message().await?;
+    charlie.expect_message().await?;
+
+    let to_bravo = r#"<event uid="chat-1" type="b-t-f"><detail><marti><dest callsign="Bravo"/></marti></detail></event>"#;
+    alpha.send_raw(to_bravo.as_bytes()).await?;
+    assert_eq!(bravo.expect_message().await?.uid(), Some("chat-1"));
+    charlie.expect_no_message().await?;
+
+    let to_charlie = r#"<event uid="marker-1" type="a-u-G"><detail><marti><dest uid="C"/></marti></detail></event>"#;
+    bravo.send_raw(to_charlie.as_bytes()).await?;
+    assert_eq!(charlie.expect_message().await?.uid(), Some("marker-1"));
+    alpha.expect_no_message().await?;
+
+    let to_all = r#"<event uid="marker-2" type="a-u-G"><detail><marti></marti></detail></event>"#;
+    charlie.send_raw(to_all.as_bytes()).await?;
+    alpha.expect_message().await?;
+    bravo.expect_message().await?;
+
+    alpha.shutdown().await?;
+    bravo.shutdown().await?;
+    charlie.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_late_joiner_receives_cached_sa() -> anyhow::Result<()> {
+    init_tracing();
+
+    let _server_task = spawn_server(SA_CACHE_TEST_PORT);
+
+    let mut alpha = TestClient::setup("client_a", "localhost", SA_CACHE_TEST_PORT).await?;
+    alpha
+        .send_raw(
+            br#"<event uid="A" type="a-f-G-U-C" stale="2000-01-01T00:00:00Z"><detail/></event>"#,
+        )
+        .await?;
+    alpha
+        .send_raw(br#"<event uid="M" type="a-u-G" stale="2999-01-01T00:00:00Z"><detail/></event>"#)
+        .await?;
+
+    let mut bravo = TestClient::setup("client_b", "localhost", SA_CACHE_TEST_PORT).await?;
+    assert_eq!(bravo.expect_message().await?.uid(), Some("M"));
+    bravo.expect_no_message().await?;
+
+    alpha.shutdown().await?;
+    bravo.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dropped_contact_is_deleted() -> anyhow::Result<()> {
+    init_tracing();
+
+    let _server_task = spawn_server(CONTACT_DELETE_TEST_PORT);
+
+    let mut alpha = TestClient::setup("client_a", "localhost", CONTACT_DELETE_TEST_PORT).await?;
+    let mut bravo = TestClient::setup("client_b", "localhost", CONTACT_DELETE_TEST_PORT).await?;
+
+    bravo.send_raw(self_sa("B", "Bravo").as_bytes()).await?;
+    alpha.expect_message().await?;
+
+    bravo.shutdown().await?;
+    let delete = alpha.expect_message().await?;
+    assert_eq!(delete.event_type(), Some("t-x-d-d"));
+    let link_uid = delete
+        .detail()
+        .and_then(|detail| detail.get_child("link", minidom::NSChoice::Any))
+        .and_then(|link| link.attr("uid"));
+    assert_eq!(link_uid, Some("B"));
+
+    alpha.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xml_and_proto_clients_talk_to_each_other() -> anyhow::Result<()> {
+    init_tracing();
+
+    let _server_task = spawn_server(PROTO_NEGOTIATION_TEST_PORT);
+
+    let mut xml_client =
+        TestClient::setup("client_a", "localhost", PROTO_NEGOTIATION_TEST_PORT).await?;
+    let mut proto_client =
+        TestClient::setup("client_b", "localhost", PROTO_NEGOTIATION_TEST_PORT).await?;
+    proto_client.negotiate_protocol().await?;
+    xml_client.expect_no_message().await?;
+
+    let sa = Message::from_raw_xml(include_str!("../src/protocol/xml/fixtures/additional.xml"))?;
+    proto_client.send(sa.clone()).await?;
+    let received = xml_client.expect_message().await?;
+    assert_eq!(received.uid(), sa.uid());
+    assert_eq!(received.to_event()?.point, sa.to_event()?.point);
+
+    let alert = Message::from_raw_xml(include_str!(
+        "../src/protocol/xml/fixtures/911_alert_start.xml"
+    ))?;
+    xml_client.send(alert.clone()).await?;
+    let received = proto_client.expect_message().await?;
+    assert_eq!(received.uid(), alert.uid());
+    assert_eq!(received.event_type(), alert.event_type());
+
+    // not a valid event, can not be sent to protobuf client
+    xml_client
+        .send_raw(b"<event><abc>From xml client</abc></event>")
+        .await?;
+    proto_client.expect_no_message().await?;
+
+    xml_client.shutdown().await?;
+    proto_client.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_udp_input_reaches_clients() -> anyhow::Result<()> {
+    init_tracing();
+
+    let mut config = test_config(UDP_INPUT_TEST_PORT);
+    config.inputs.push(Input {
+        transport: Transport::Udp(RateLimit {
+            per_second: 0.001,
+            burst: 3,
+        }),
+        ..Input::udp(any_addr(UDP_INPUT_TEST_PORT))
+    });
+    let _server_task = spawn_server_with(config);
+
+    let mut client = TestClient::setup("client_a", "localhost", UDP_INPUT_TEST_PORT).await?;
+    let sensor = UdpSocket::bind("127.0.0.1:0").await?;
+    sensor.connect(("127.0.0.1", UDP_INPUT_TEST_PORT)).await?;
+
+    sensor
+        .send(br#"<event uid="sensor-1" type="a-u-G"><detail/></event>"#)
+        .await?;
+    assert_eq!(client.expect_message().await?.uid(), Some("sensor-1"));
+
+    // garbage does not stop the input, but it is limited before decoding so it takes a token
+    sensor.send(b"not a cot").await?;
+    sensor
+        .send(br#"<event uid="sensor-2" type="a-u-G"><detail/></event>"#)
+        .await?;
+    assert_eq!(client.expect_message().await?.uid(), Some("sensor-2"));
+
+    // burst of 3 is used up
+    sensor
+        .send(br#"<event uid="sensor-3" type="a-u-G"><detail/></event>"#)
+        .await?;
+    client.expect_no_message().await?;
+
+    client.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mesh_bridge_forwards_both_ways_without_echo() -> anyhow::Result<()> {
+    init_tracing();
+
+    let mesh_config = multicast::Config {
+        group: SocketAddrV4::new(Ipv4Addr::new(239, 2, 3, 1), MESH_BRIDGE_TEST_PORT),
+        interface: Ipv4Addr::LOCALHOST,
+        encoding: MeshEncoding::Xml,
+        rate_limit: Default::default(),
+    };
+    let _server_task = spawn_server_with(Config {
+        mesh_bridge: Some(mesh_config),
+        ..test_config(MESH_BRIDGE_TEST_PORT)
+    });
+
+    let mut alpha = TestClient::setup("client_a", "localhost", MESH_BRIDGE_TEST_PORT).await?;
+    let mut bravo = TestClient::setup("client_b", "localhost", MESH_BRIDGE_TEST_PORT).await?;
+    let mesh_device = multicast::bind(&mesh_config)?;
+    let mut datagram = vec![0u8; 4096];
+
+    mesh_device
+        .send_to(
+            br#"<event uid="mesh-1" type="a-f-G-U-C"><detail/></event>"#,
+            mesh_config.group,
+        )
+        .await?;
+    assert_eq!(alpha.expect_message().await?.uid(), Some("mesh-1"));
+    assert_eq!(bravo.expect_message().await?.uid(), Some("mesh-1"));
+    // own datagram through loopback
+    mesh_device.recv_from(&mut datagram).await?;
+
+    bravo
+        .send_raw(br#"<event uid="server-1" type="a-f-G-U-C"><detail/></event>"#)
+        .await?;
+    assert_eq!(alpha.expect_message().await?.uid(), Some("server-1"));
+    let (len, _) = tokio::time::timeout(
+        Duration::from_millis(100),
+        mesh_device.recv_from(&mut datagram),
+    )
+    .await??;
+    assert_eq!(
+        Message::from_raw_xml(std::str::from_utf8(&datagram[..len])?)?.uid(),
+        Some("server-1")
+    );
+    // re-emitted event is not bridged back
+    alpha.expect_no_message().await?;
+    bravo.expect_no_message().await?;
+
+    alpha.shutdown().await?;
+    bravo.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_only_plain_tcp_input() -> anyhow::Result<()> {
+    init_tracing();
+
+    let mut config = test_config(PLAIN_TCP_TEST_PORT);
+    config.inputs.push(Input {
+        direction: Direction::ReadOnly,
+        ..Input::tcp(any_addr(PLAIN_TCP_INPUT_PORT))
+    });
+    let _server_task = spawn_server_with(config);
+
+    let mut client = TestClient::setup("client_a", "localhost", PLAIN_TCP_TEST_PORT).await?;
+    let mut sensor = TcpStream::connect(("127.0.0.1", PLAIN_TCP_INPUT_PORT)).await?;
+
+    sensor
+        .write_all(br#"<event uid="sensor-1" type="a-u-G"><detail/></event>"#)
+        .await?;
+    assert_eq!(client.expect_message().await?.uid(), Some("sensor-1"));
+
+    client
+        .send_raw(br#"<event uid="client-1" type="a-u-G"><detail/></event>"#)
+        .await?;
+    let mut buff = [0u8; 1024];
+    // neither protocol advertisement nor routed events are written to read-only input
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), sensor.read(&mut buff))
+            .await
+            .is_err()
+    );
+
+    client.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multiple_inputs_share_router() -> anyhow::Result<()> {
+    init_tracing();
+
+    let mut config = test_config(MULTI_INPUT_TLS_PORT);
+    config.inputs = vec![
+        // dual-stack, test client may resolve localhost to either address family
+        Input::tls(SocketAddr::from((
+            Ipv6Addr::UNSPECIFIED,
+            MULTI_INPUT_TLS_PORT,
+        ))),
+        Input {
+            ipv6_only: true,
+            protocol: Protocol::Proto,
+            ..Input::tcp(SocketAddr::from((
+                Ipv6Addr::LOCALHOST,
+                MULTI_INPUT_PROTO_PORT,
+            )))
+        },
+        Input {
+            protocol: Protocol::Xml,
+            ..Input::tcp(SocketAddr::from((
+                Ipv4Addr::LOCALHOST,
+                MULTI_INPUT_XML_PORT,
+            )))
+        },
+    ];
+    let _server_task = spawn_server_with(config);
+
+    let mut client = TestClient::setup("client_a", "localhost", MULTI_INPUT_TLS_PORT).await?;
+    let proto_sensor = TcpStream::connect((Ipv6Addr::LOCALHOST, MULTI_INPUT_PROTO_PORT)).await?;
+    let mut proto_sensor = Framed::new(proto_sensor, TakProtoCodec::new(64 * 1024));
+    let xml_sensor = TcpStream::connect((Ipv4Addr::LOCALHOST, MULTI_INPUT_XML_PORT)).await?;
+    let mut xml_sensor = Framed::new(xml_sensor, CotLegacyCodec::new(10 * 1024));
+
+    let sa = Message::from_raw_xml(include_str!("../src/protocol/xml/fixtures/additional.xml"))?;
+    proto_sensor.send(sa.clone()).await?;
+    assert_eq!(client.expect_message().await?.uid(), sa.uid());
+    assert_eq!(
+        tokio::time::timeout(Duration::from_millis(100), xml_sensor.next())
+            .await?
+            .expect("routed to xml input")?
+            .uid(),
+        sa.uid()
+    );
+
+    client
+        .send_raw(br#"<event uid="client-1" type="a-u-G"><detail/></event>"#)
+        .await?;
+    // not a valid event, so only the xml input gets it
+    let received = tokio::time::timeout(Duration::from_millis(100), xml_sensor.next())
+        .await?
+        .expect("routed to xml input")?;
+    assert_eq!(received.uid(), Some("client-1"));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), proto_sensor.next())
+            .await
+            .is_err()
+    );
+
+    client.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_drains_connections() -> anyhow::Result<()> {
+    init_tracing();
+
+    let mut config = test_config(SHUTDOWN_TEST_PORT);
+    config.inputs.push(Input {
+        direction: Direction::ReadOnly,
+        ..Input::tcp(any_addr(SHUTDOWN_PLAIN_TCP_PORT))
+    });
+    let server = Server::new(config)?;
+    let shutdown = server.shutdown_token();
+    let server_task = tokio::spawn(server.run());
+
+    let mut alpha = TestClient::setup("client_a", "localhost", SHUTDOWN_TEST_PORT).await?;
+    let mut bravo = TestClient::setup("client_b", "localhost", SHUTDOWN_TEST_PORT).await?;
+    let mut sensor = TcpStream::connect(("127.0.0.1", SHUTDOWN_PLAIN_TCP_PORT)).await?;
+
+    alpha.send_raw(self_sa("alpha", "ALPHA").as_bytes()).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.cancel();
+
+    // message queued before shutdown is still delivered, then streams end
+    assert_eq!(bravo.expect_message().await?.uid(), Some("alpha"));
+    bravo.expect_closed().await?;
+    alpha.expect_closed().await?;
+    let mut buff = [0u8; 1024];
+    let read = tokio::time::timeout(Duration::from_secs(1), sensor.read(&mut buff)).await??;
+    assert_eq!(read, 0, "read-only connection closed");
+
+    tokio::time::timeout(Duration::from_secs(2), server_task).await???;
+    assert!(TcpStream::connect(("127.0.0.1", SHUTDOWN_TEST_PORT))
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silent_clients_are_disconnected() -> anyhow::Result<()> {
+    init_tracing();
+
+    let _server_task = spawn_server_with(Config {
+        timeouts: Timeouts {
+            tls_handshake: Some(Duration::from_millis(200)),
+            first_event: Some(Duration::from_millis(200)),
+            idle: Some(Duration::from_millis(400)),
+            ping_interval: None,
+        },
+        ..test_config(HANDSHAKE_TIMEOUT_TEST_PORT)
+    });
+
+    let mut client =
+        TestClient::setup("client_a", "localhost", HANDSHAKE_TIMEOUT_TEST_PORT).await?;
+    // never starts TLS
+    let mut silent = TcpStream::connect(("127.0.0.1", HANDSHAKE_TIMEOUT_TEST_PORT)).await?;
+    let mut buff = [0u8; 1024];
+    let read = tokio::time::timeout(Duration::from_secs(1), silent.read(&mut buff)).await??;
+    assert_eq!(read, 0, "connection without handshake closed");
+
+    // TLS client which sent nothing yet
+    client.expect_closed().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xml_detail_limits_apply_to_protobuf_clients() -> anyhow::Result<()> {
+    init_tracing();
+
+    let mut config = Config {
+        xml_limits: XmlLimits {
+            max_depth: 6,
+            ..Default::default()
+        },
+        ..test_config(XML_DETAIL_LIMITS_TEST_PORT)
+    };
+    config.inputs.extend([
+        Input {
+            protocol: Protocol::Proto,
+            ..Input::tcp(any_addr(XML_DETAIL_LIMITS_PROTO_PORT))
+        },
+        Input::udp(any_addr(XML_DETAIL_LIMITS_PROTO_PORT)),
+    ]);
+    let _server_task = spawn_server_with(config);
+
+    let mut client =
+        TestClient::setup("client_a", "localhost", XML_DETAIL_LIMITS_TEST_PORT).await?;
+    let mut negotiated =
+        TestClient::setup("client_b", "localhost", XML_DETAIL_LIMITS_TEST_PORT).await?;
+    negotiated.negotiate_protocol().await?;
+    client.expect_no_message().await?;
+
+    // unknown detail children end up in xmlDetail
+    let deep_sa = Message::from_raw_xml(
+        &include_str!("../src/protocol/xml/fixtures/additional.xml").replace(
+            "<detail>",
+            &format!("<detail>{}{}", "<a>".repeat(8), "</a>".repeat(8)),
+        ),
+    )?;
+
+    negotiated.send(deep_sa.clone()).await?;
+    negotiated.expect_disconnected().await?;
+    client.expect_no_message().await?;
+
+    let proto_sensor = TcpStream::connect(("127.0.0.1", XML_DETAIL_LIMITS_PROTO_PORT)).await?;
+    let mut proto_sensor = Framed::new(proto_sensor, TakProtoCodec::new(64 * 1024));
+    proto_sensor.send(deep_sa.clone()).await?;
+    assert!(matches!(
+        tokio::time::timeout(Duration::from_secs(1), proto_sensor.next()).await?,
+        None | Some(Err(_))
+    ));
+    client.expect_no_message().await?;
+
+    let udp_sensor = UdpSocket::bind("127.0.0.1:0").await?;
+    udp_sensor
+        .connect(("127.0.0.1", XML_DETAIL_LIMITS_PROTO_PORT))
+        .await?;
+    let mut datagram = MESH_HEADER.to_vec();
+    deep_sa.to_tak_message()?.encode(&mut datagram)?;
+    udp_sensor.send(&datagram).await?;
+    client.expect_no_message().await?;
+
+    client.shutdown().await?;
+    Ok(())
+}
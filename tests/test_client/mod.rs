@@ -2,9 +2,10 @@ use anyhow::{anyhow, Context};
 use futures::{SinkExt, StreamExt};
 use std::sync::Arc;
 use std::time::Duration;
-use tak_rs::protocol::xml::CotLegacyCodec;
+use tak_rs::protocol::negotiation::{self, StreamCodec, TAK_PROTO_VERSION};
 use tak_rs::protocol::Message;
 use tak_rs::tls;
+use time::OffsetDateTime;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
@@ -12,7 +13,7 @@ use tokio_rustls::TlsConnector;
 use tokio_util::codec::Framed;
 
 pub struct TestClient {
-    frames: Framed<TlsStream<TcpStream>, CotLegacyCodec>,
+    frames: Framed<TlsStream<TcpStream>, StreamCodec>,
 }
 
 impl TestClient {
@@ -27,14 +28,38 @@ impl TestClient {
         tokio::time::sleep(Duration::from_secs(1)).await;
         let client = TcpStream::connect((host, port)).await?;
         let conn = tls_connector.connect(host.try_into()?, client).await?;
-        Ok(Self {
-            frames: Framed::new(conn, CotLegacyCodec::new(10 * 1024)),
-        })
+        let mut client = Self {
+            frames: Framed::new(conn, StreamCodec::xml(10 * 1024)),
+        };
+
+        // server always starts with protocol version advertisement
+        let support = client.expect_message().await?;
+        if support.event_type() != Some(negotiation::VERSION_SUPPORT_TYPE) {
+            return Err(anyhow!("expected protocol support, got: {support:?}"));
+        }
+        Ok(client)
+    }
+
+    /// switches connection to TAK protocol version 1
+    pub async fn negotiate_protocol(&mut self) -> anyhow::Result<()> {
+        self.send(negotiation::version_request(
+            TAK_PROTO_VERSION,
+            OffsetDateTime::now_utc(),
+        ))
+        .await?;
+        let response = self.expect_message().await?;
+        if negotiation::response_status(&response) != Some(true) {
+            return Err(anyhow!("protocol request rejected: {response:?}"));
+        }
+        self.frames
+            .codec_mut()
+            .upgrade(64 * 1024, Default::default());
+        Ok(())
     }
 
     pub async fn send_raw(&mut self, data: &[u8]) -> anyhow::Result<()> {
-        self.frames.get_mut().write(data).await?;
-        self.frames.flush().await?;
+        self.frames.get_mut().write_all(data).await?;
+        SinkExt::<Message>::flush(&mut self.frames).await?;
         Ok(())
     }
 
@@ -43,7 +68,7 @@ impl TestClient {
         Ok(())
     }
 
-    pub async fn _expect_message(&mut self) -> anyhow::Result<Message> {
+    pub async fn expect_message(&mut self) -> anyhow::Result<Message> {
         let msg_res = tokio::select! {
             msg = self.frames.next() => msg,
             _ = tokio::time::sleep(Duration::from_millis(100)) => return Err(anyhow!("timeout waiting for message"))
@@ -54,6 +79,31 @@ impl TestClient {
         Ok(msg)
     }
 
+    pub async fn expect_no_message(&mut self) -> anyhow::Result<()> {
+        tokio::select! {
+            msg = self.frames.next() => Err(anyhow!("unexpected message: {msg:?}")),
+            _ = tokio::time::sleep(Duration::from_millis(100)) => Ok(())
+        }
+    }
+
+    /// server ends the stream cleanly, after everything sent before
+    pub async fn expect_closed(&mut self) -> anyhow::Result<()> {
+        match tokio::time::timeout(Duration::from_secs(1), self.frames.next()).await {
+            Ok(None) => Ok(()),
+            Ok(Some(msg)) => Err(anyhow!("unexpected message: {msg:?}")),
+            Err(_) => Err(anyhow!("timeout waiting for end of stream")),
+        }
+    }
+
+    /// server drops the connection, with or without TLS close_notify
+    pub async fn expect_disconnected(&mut self) -> anyhow::Result<()> {
+        match tokio::time::timeout(Duration::from_secs(1), self.frames.next()).await {
+            Ok(None | Some(Err(_))) => Ok(()),
+            Ok(Some(Ok(msg))) => Err(anyhow!("unexpected message: {msg:?}")),
+            Err(_) => Err(anyhow!("timeout waiting for disconnection")),
+        }
+    }
+
     pub async fn shutdown(mut self) -> anyhow::Result<()>
     where
         Self: Unpin,